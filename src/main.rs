@@ -1,6 +1,8 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use crate::packages::log_parser::LogParser;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use crate::LogFormat::Linux;
 use crate::LogFormat::OpenStack;
 use crate::LogFormat::Spark;
@@ -14,6 +16,10 @@ mod packages;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+// what a session key is used for
+#[command(group(clap::ArgGroup::new("session_output").multiple(true).args(["sessions", "count_matrix", "transitions"])))]
+// what numbers templates for --template-table
+#[command(group(clap::ArgGroup::new("template_ids").multiple(true).args(["sessions", "count_matrix", "transitions", "time_series"])))]
 struct Args {
    /// Name of the raw logfile to convert to a CSV
    #[arg(long)]
@@ -41,7 +47,7 @@ struct Args {
    raw_healthapp: Option<String>,
 
    #[arg(long)]
-   to_parse: String,
+   to_parse: Option<String>,
 
    #[arg(long)]
    before: Option<String>,
@@ -63,20 +69,241 @@ struct Args {
 
    #[arg(long,default_value="8")]
    num_threads: Option<u32>,
+
+   /// Saved dictionary to update incrementally (used with --checkpoint)
+   #[arg(long,requires="checkpoint")]
+   dictionary: Option<String>,
+
+   /// Where the last run stopped reading the raw log; only bytes appended since then are parsed
+   #[arg(long,requires="dictionary")]
+   checkpoint: Option<String>,
+
+   /// Only count n-grams from the last N lines of the file
+   #[arg(long,value_parser=clap::value_parser!(i64).range(1..),conflicts_with_all=["checkpoint","decay_half_life","window_time"])]
+   window_lines: Option<i64>,
+
+   /// Only count n-grams from the lines of the last stretch of time before the file's last timestamp, e.g. 30m or 1h
+   #[arg(long,value_parser=duration,conflicts_with_all=["checkpoint","decay_half_life"])]
+   window_time: Option<i64>,
+
+   /// Count n-grams with weights that halve every N lines, so recent lines dominate
+   #[arg(long,value_parser=positive,conflicts_with="checkpoint")]
+   decay_half_life: Option<f64>,
+
+   /// Log to scan for lines that look like nothing in the raw (training) log
+   #[arg(long)]
+   detect: Option<String>,
+
+   /// Log whose lines to rank by how unusual their token sequences are, rarest first
+   #[arg(long)]
+   score: Option<String>,
+
+   /// Combine per-token probabilities into a line score by their mean or minimum
+   #[arg(long,default_value="mean",value_parser=["mean","min"])]
+   score_by: String,
+
+   /// Write the dictionaries as an ARPA 3-gram language model
+   #[arg(long)]
+   export_arpa: Option<String>,
+
+   /// Take the dictionaries from an ARPA language model instead of parsing the raw log
+   #[arg(long,conflicts_with_all=["checkpoint","window_lines","window_time","decay_half_life"])]
+   import_arpa: Option<String>,
+
+   /// Print the templates of the raw log, with how many lines each covers
+   #[arg(long)]
+   templates: bool,
+
+   /// Which parser extracts templates
+   #[arg(long,default_value="logram",value_parser=["logram","drain","spell"])]
+   parser: String,
+
+   /// Also split Linux and HPC content on punctuation and key=value, and strip brackets, so that rhost=218.22.3.51 gives rhost= and <IP>
+   #[arg(long)]
+   split_punctuation: bool,
+
+   /// Depth of the Drain parse tree, counting the root and the token-count level
+   #[arg(long,default_value="4")]
+   drain_depth: usize,
+
+   /// How similar a line must be to a Drain cluster to join it
+   #[arg(long,default_value="0.4")]
+   drain_sim: f64,
+
+   /// Fraction of a line that must be in a Spell template's longest common subsequence to join it
+   #[arg(long,default_value="0.5")]
+   spell_tau: f64,
+
+   /// Time every parser on the raw log and compare their templates
+   #[arg(long)]
+   benchmark: bool,
+
+   /// Event ids for the raw log (a Loghub *_structured.csv, or one id per line) to measure --benchmark accuracy
+   #[arg(long)]
+   ground_truth: Option<String>,
+
+   /// Compress the raw log into this file, as a template table and parameter columns
+   #[arg(long)]
+   compress: Option<String>,
+
+   /// Decompress a file written by --compress into --output
+   #[arg(long,requires="output")]
+   decompress: Option<String>,
+
+   /// Index the raw log into this file by template, for --search
+   #[arg(long)]
+   index: Option<String>,
+
+   /// Index written by --index to look lines up in; lists the templates without --template
+   #[arg(long)]
+   search: Option<String>,
+
+   /// Template to --search for, by id or text
+   #[arg(long,requires="search")]
+   template: Option<String>,
+
+   /// Only lines whose parameter has this value, as SLOT=VALUE; SLOT is a position, a label such as <IP>, or its context such as rhost=<IP>
+   #[arg(long,requires="template")]
+   param: Option<String>,
+
+   /// Where --decompress writes the log
+   #[arg(long)]
+   output: Option<String>,
+
+   /// Year of timestamps that leave it out, as syslog does (default: this year)
+   #[arg(long)]
+   year: Option<i64>,
+
+   /// Only use raw log lines from this time on, e.g. 2005-06-23T22:00
+   #[arg(long,value_parser=time,conflicts_with_all=["checkpoint","window_lines","window_time","decay_half_life","import_arpa","compress","index"])]
+   since: Option<i64>,
+
+   /// Only use raw log lines from before this time
+   #[arg(long,value_parser=time,conflicts_with_all=["checkpoint","window_lines","window_time","decay_half_life","import_arpa","compress","index"])]
+   until: Option<i64>,
+
+   /// With --since/--until, what to do with lines that have no timestamp: take the previous line's, or always include or exclude them
+   #[arg(long,value_enum,default_value="inherit")]
+   untimed: packages::timestamp::Untimed,
+
+   /// Write how often each template occurs per --bucket of time to this CSV file
+   #[arg(long)]
+   time_series: Option<String>,
+
+   /// Length of the --time-series buckets, e.g. 30s, 5m, 1h or 1d
+   #[arg(long,default_value="1m",value_parser=duration)]
+   bucket: i64,
+
+   /// Write the time series with a column per template instead of a row per template and bucket
+   #[arg(long,requires="time_series")]
+   wide: bool,
+
+   /// Print the --bucket windows where a template occurs much more or less often than before, or stops
+   #[arg(long)]
+   rate_changes: bool,
+
+   /// How improbable a count must be under a template's earlier rate for --rate-changes to report it
+   #[arg(long,default_value="0.001")]
+   alpha: f64,
+
+   /// Buckets of history a template needs before --rate-changes tests it
+   #[arg(long,default_value="5")]
+   min_history: usize,
+
+   /// Group the raw log into sessions and write each session's sequence of template ids to this CSV file
+   #[arg(long,requires="session_key")]
+   sessions: Option<String>,
+
+   /// Regexp whose matches in the raw line (or first group) identify the --sessions a line belongs to, e.g. blk_-?\d+
+   #[arg(long,group="session_key",requires="session_output")]
+   session_pattern: Option<String>,
+
+   /// Header field that identifies a line's session, e.g. Pid
+   #[arg(long,group="session_key",requires="session_output")]
+   session_field: Option<String>,
+
+   /// Write the template behind each template id of --sessions, --count-matrix, --transitions or --time-series to this CSV file
+   #[arg(long,requires="template_ids")]
+   template_table: Option<String>,
+
+   /// Write an event count matrix to this file: a row per session (with --session-pattern or --session-field) or per --bucket, a column per template id
+   #[arg(long)]
+   count_matrix: Option<String>,
+
+   /// Weigh the --count-matrix by TF-IDF
+   #[arg(long,requires="count_matrix")]
+   tf_idf: bool,
+
+   /// Write the --count-matrix as little-endian f64s after a header instead of CSV
+   #[arg(long,requires="count_matrix")]
+   binary: bool,
+
+   /// Write which templates follow which, within sessions if --session-pattern or --session-field is given, as a Graphviz DOT file
+   #[arg(long)]
+   transitions: Option<String>,
+
+   /// Leave transitions seen fewer times than this out of the --transitions graph
+   #[arg(long,default_value="1")]
+   min_edge: usize,
+
+   /// Print the templates that most often occur in the --window before lines of this template (an id from --template-table, or the template itself)
+   #[arg(long)]
+   cooccur: Option<String>,
+
+   /// How far before each line --cooccur looks: a number of lines, or a time such as 30s or 5m
+   #[arg(long,default_value="10",value_parser=window)]
+   window: packages::cooccurrence::Window,
+
+   /// How many templates --cooccur prints
+   #[arg(long,default_value="20")]
+   top: usize,
+
+   /// Print, for every <*> of every template, how many distinct values it takes, the most frequent ones, their range if numeric, and examples
+   #[arg(long)]
+   param_stats: bool,
+
+   /// How many of the most frequent values --param-stats prints
+   #[arg(long,default_value="5")]
+   top_values: usize,
+
+   /// Refine the parser's templates by what their positions hold, for --templates and the analyses built on them: see --split-max and --merge-min
+   #[arg(long)]
+   refine: bool,
+
+   /// With --refine, make a <*> static if it takes at most this many distinct values
+   #[arg(long,default_value="2",requires="refine")]
+   split_max: usize,
+
+   /// With --refine, merge templates that differ at one position only if it takes at least this many distinct values
+   #[arg(long,default_value="4",requires="refine")]
+   merge_min: usize,
+
+   /// Print the dynamic tokens of --to-parse as JSON, with their index and byte span in the line
+   #[arg(long)]
+   json: bool,
+}
+
+// for clap: a duration such as 30s or 5m, in milliseconds
+fn duration(s: &str) -> Result<i64, String> {
+    packages::timestamp::parse_duration(s).ok_or_else(|| "durations look like 30s, 5m, 1h or 1d".to_string())
 }
 
-#[test]
-fn test_derive_2grams_from_trigram() {
-    let twograms_oracle = vec![("one^two"), 
-                               ("two^three")];
-    let twograms = derive_2grams_from_trigram("one^two^three");
-    assert_eq!(twograms_oracle, twograms);
+// for clap: a time such as 2005-06-23T22:00:00, in milliseconds since 1970
+fn time(s: &str) -> Result<i64, String> {
+    packages::timestamp::parse_time(s).ok_or_else(|| "times look like 2005-06-23T22:00:00".to_string())
 }
 
-fn derive_2grams_from_trigram(trigram:&str) -> Vec<String> {
-    let grams : Vec<&str> = trigram.split("^").collect();
-    return vec![format!("{}^{}", grams[0], grams[1]), 
-                format!("{}^{}", grams[1], grams[2])];
+// for clap: a --cooccur window
+fn window(s: &str) -> Result<packages::cooccurrence::Window, String> {
+    packages::cooccurrence::parse_window(s).ok_or_else(|| "windows look like 10 (lines) or 5m".to_string())
+}
+
+// for clap: a number above 0
+fn positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err("must be a number above 0".to_string()),
+    }
 }
 
 pub enum LogFormat {
@@ -95,131 +322,464 @@ fn view_double_and_triple_dicts(double_dict:&HashMap<String, i32>, triple_dict:&
     packages::parser::print_dict("triple", triple_dict);
 }
 
+
+// adds what was being done to an error, for the one message main prints
+fn context(what: &'static str) -> impl Fn(io::Error) -> io::Error {
+    move |e| io::Error::new(e.kind(), format!("{}: {}", what, e))
+}
+
+// an error for input that was read but is of no use
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, what.to_string())
+}
+
+// writes a new file at `path` through a buffer
+fn write_file(path: &str, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out)?;
+    out.flush()
+}
+
 fn main() {
     let args = Args::parse();
+    if args.refine && args.split_max >= args.merge_min {
+        Args::command().error(clap::error::ErrorKind::ArgumentConflict, "--split-max must be below --merge-min, or merging undoes the splits").exit();
+    }
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
 
-    let mut input_fn = None;
-    let mut log_format_opt = None;
-    // hey, please let me know (email) if there's a more idiomatic way to do this
-    if let Some(raw_linux) = args.raw_linux {
-        log_format_opt = Some(Linux);
-        input_fn = Some(raw_linux);
-    } else if let Some(raw_openstack) = args.raw_openstack {
-        log_format_opt = Some(OpenStack);
-        input_fn = Some(raw_openstack);
-    } else if let Some(raw_spark) = args.raw_spark {
-        log_format_opt = Some(Spark);
-        input_fn = Some(raw_spark);
-    } else if let Some(raw_hdfs) = args.raw_hdfs {
-        log_format_opt = Some(HDFS);
-        input_fn = Some(raw_hdfs);
-    } else if let Some(raw_hpc) = args.raw_hpc {
-        log_format_opt = Some(HPC);
-        input_fn = Some(raw_hpc);
-    } else if let Some(raw_proxifier) = args.raw_proxifier {
-        log_format_opt = Some(Proxifier);
-        input_fn = Some(raw_proxifier);
-    } else if let Some(raw_android) = args.raw_android {
-        log_format_opt = Some(Android);
-        input_fn = Some(raw_android);
-    } else if let Some(raw_healthapp) = args.raw_healthapp {
-        log_format_opt = Some(HealthApp);
-        input_fn = Some(raw_healthapp);
-    }
-    let log_format = match log_format_opt {
-        None => panic!("must specify a raw input file"),
-        Some(lf) => lf,
+fn run(args: &Args) -> io::Result<()> {
+    // these need neither a format nor dictionaries
+    if let Some(compressed_fn) = &args.decompress {
+        return decompress(compressed_fn, args.output.as_ref().unwrap());
+    }
+    if let Some(index_fn) = &args.search {
+        return search(args, index_fn);
+    }
+
+    let (log_format, input_fn) = raw_input(args).ok_or_else(|| invalid("must specify a raw input file"))?;
+    let config = match args.split_punctuation {
+        true => packages::parser::FormatConfig::split_punctuation(&log_format),
+        false => packages::parser::FormatConfig::new(&log_format),
+    };
+    if let Some(field) = &args.session_field {
+        let fields: Vec<&str> = config.re.capture_names().flatten().filter(|f| *f != "Content").collect();
+        if !fields.contains(&field.as_str()) {
+            Args::command().error(clap::error::ErrorKind::InvalidValue, format!("--session-field must be one of the format's header fields: {}", fields.join(", "))).exit();
+        }
+    }
+
+    let year = args.year.unwrap_or_else(packages::timestamp::current_year);
+    let in_window = match (args.since, args.until) {
+        (None, None) => None,
+        (since, until) => {
+            let lines = packages::parser::read_log_lines(&input_fn).map_err(context("could not read log"))?;
+            let timestamps = packages::timestamp::TimestampParser::new(&log_format, year);
+            let line_nos = timestamps.in_window(&lines, since, until, &args.untimed);
+            if line_nos.is_empty() {
+                println!("no line of the log is between --since and --until; timestamps without a year are read as {}, see --year", year);
+            }
+            let kept: Vec<String> = line_nos.iter().map(|n| lines[n - 1].clone()).collect();
+            Some((line_nos, kept))
+        }
+    };
+
+    let mut input = Input { args, log_format, input_fn, config, year, in_window, double_dict: HashMap::new(), triple_dict: HashMap::new(), all_token_list: vec![] };
+    (input.double_dict, input.triple_dict, input.all_token_list) = dictionaries(&input)?;
+    view_double_and_triple_dicts(&input.double_dict, &input.triple_dict);
+
+    if let Some(arpa_fn) = &args.export_arpa {
+        packages::arpa::export_arpa(arpa_fn, &input.double_dict, &input.triple_dict, &input.all_token_list).map_err(context("could not export ARPA model"))?;
+    }
+    analyses(&input)?;
+    if let Some(compressed_fn) = &args.compress {
+        compress(&input, compressed_fn)?;
+    }
+    if let Some(index_fn) = &args.index {
+        index(&input, index_fn)?;
+    }
+    if args.benchmark {
+        benchmark(&input)?;
+    }
+    if let Some(detect_fn) = &args.detect {
+        detect(&input, detect_fn)?;
+    }
+    if let Some(score_fn) = &args.score {
+        score(&input, score_fn)?;
+    }
+    if let Some(to_parse) = &args.to_parse {
+        parse_sample(&input, to_parse);
+    }
+    Ok(())
+}
+
+fn decompress(compressed_fn: &str, output_fn: &str) -> io::Result<()> {
+    let compressed = std::fs::read(compressed_fn).map_err(context("could not read compressed log"))?;
+    let bytes = packages::compress::decompress(&compressed).map_err(context("could not decompress log"))?;
+    std::fs::write(output_fn, bytes).map_err(context("could not write log"))
+}
+
+fn search(args: &Args, index_fn: &str) -> io::Result<()> {
+    let index = packages::index::load_index(index_fn).map_err(context("could not read index"))?;
+    let template = match &args.template {
+        None => {
+            packages::index::print_index(&index);
+            return Ok(());
+        }
+        Some(t) => t,
     };
-    let cutoff = args.cutoff.unwrap();
+    let entry = &index.templates[index.find_template(template).ok_or_else(|| invalid("no such template in index"))?];
+    let constraint = match &args.param {
+        Some(c) => Some(entry.constraint(c).ok_or_else(|| invalid("no such parameter in template"))?),
+        None => None,
+    };
+    let found = entry.search(constraint);
+    let lines = packages::index::read_postings(&index, &found).map_err(context("could not read indexed log"))?;
+    for (p, line) in found.iter().zip(lines) {
+        println!("{}\t{}", p.line_no, String::from_utf8_lossy(&line));
+    }
+    Ok(())
+}
+
+// the format and path of the --raw-* log given
+fn raw_input(args: &Args) -> Option<(LogFormat, String)> {
+    // hey, please let me know (email) if there's a more idiomatic way to do this
+    if let Some(raw_linux) = &args.raw_linux {
+        Some((Linux, raw_linux.clone()))
+    } else if let Some(raw_openstack) = &args.raw_openstack {
+        Some((OpenStack, raw_openstack.clone()))
+    } else if let Some(raw_spark) = &args.raw_spark {
+        Some((Spark, raw_spark.clone()))
+    } else if let Some(raw_hdfs) = &args.raw_hdfs {
+        Some((HDFS, raw_hdfs.clone()))
+    } else if let Some(raw_hpc) = &args.raw_hpc {
+        Some((HPC, raw_hpc.clone()))
+    } else if let Some(raw_proxifier) = &args.raw_proxifier {
+        Some((Proxifier, raw_proxifier.clone()))
+    } else if let Some(raw_android) = &args.raw_android {
+        Some((Android, raw_android.clone()))
+    } else {
+        args.raw_healthapp.as_ref().map(|raw_healthapp| (HealthApp, raw_healthapp.clone()))
+    }
+}
+
+/// The raw log and what was built from it, which the modes after the dictionaries work from.
+struct Input<'a> {
+    args: &'a Args,
+    log_format: LogFormat,
+    input_fn: String,
+    config: packages::parser::FormatConfig,
+    year: i64,
+    /// the numbers and lines of the raw log inside --since/--until, if given
+    in_window: Option<(Vec<usize>, Vec<String>)>,
+    double_dict: HashMap<String, i32>,
+    triple_dict: HashMap<String, i32>,
+    all_token_list: Vec<String>,
+}
+
+impl Input<'_> {
+    fn cutoff(&self) -> i32 {
+        self.args.cutoff.unwrap()
+    }
+
+    /// The lines of the raw log, or of the --since/--until window.
+    fn read(&self) -> io::Result<Vec<String>> {
+        match &self.in_window {
+            Some((_, lines)) => Ok(lines.clone()),
+            None => packages::parser::read_log_lines(&self.input_fn).map_err(context("could not read log")),
+        }
+    }
+
+    fn timestamps(&self) -> packages::timestamp::TimestampParser {
+        packages::timestamp::TimestampParser::new(&self.log_format, self.year)
+    }
+
+    /// A parser on the dictionaries already built.
+    fn ngram_parser(&self) -> packages::log_parser::NgramParser {
+        packages::log_parser::NgramParser::with_dictionaries(&self.config, self.cutoff(), self.double_dict.clone(), self.triple_dict.clone())
+    }
 
-    let num_of_threads = args.num_threads.unwrap();
+    /// The --parser chosen, trained on `lines`.
+    fn trained_parser(&self, lines: &[String]) -> Box<dyn packages::log_parser::LogParser> {
+        let args = self.args;
+        match args.parser.as_str() {
+            "drain" => {
+                let mut drain = packages::drain::DrainParser::new(&self.config, args.drain_depth, args.drain_sim);
+                drain.train(lines);
+                Box::new(drain)
+            }
+            "spell" => {
+                let mut spell = packages::spell::SpellParser::new(&self.config, args.spell_tau);
+                spell.train(lines);
+                Box::new(spell)
+            }
+            // the logram dictionaries are already built, however they were asked for
+            _ => Box::new(self.ngram_parser()),
+        }
+    }
+}
 
-    let mut double_dict = HashMap::new();
-    let mut triple_dict = HashMap::new();
-    let mut _all_token_list = vec![];
+fn dictionaries(input: &Input) -> io::Result<packages::dictionary::Dictionaries> {
+    let args = input.args;
+    let num_of_threads = args.num_threads.unwrap().try_into().unwrap();
+    let counting = match (args.window_lines, args.window_time, args.decay_half_life) {
+        (Some(n), _, _) => Some(packages::window::Counting::LastLines(n)),
+        (None, Some(t), _) => Some(packages::window::Counting::LastTime(t)),
+        (None, None, Some(h)) => Some(packages::window::Counting::HalfLife(h)),
+        (None, None, None) => None,
+    };
 
-    match args.single_map {
-        Some(value) => {
+    Ok(match (args.single_map, &args.checkpoint) {
+        _ if args.import_arpa.is_some() => {
+            packages::arpa::import_arpa(args.import_arpa.as_ref().unwrap()).map_err(context("could not import ARPA model"))?
+        }
+        (single_map, _) if input.in_window.is_some() => {
+            packages::parser::parse_lines(input.read()?, &input.config, single_map.unwrap_or(false), num_of_threads)
+        }
+        _ if counting.is_some() => {
+            packages::window::streaming_parse_raw(&input.input_fn, &input.config, &input.timestamps(), counting.as_ref().unwrap()).map_err(context("could not read raw log"))?
+        }
+        (_, Some(checkpoint_fn)) => {
+            packages::incremental::incremental_parse_raw(&input.input_fn, &input.config, args.dictionary.as_ref().unwrap(), checkpoint_fn).map_err(context("incremental dictionary update failed"))?
+        }
+        (Some(value), None) => {
             println!("{}", value);
-            (double_dict, triple_dict, _all_token_list) = packages::parser::parse_raw(input_fn.unwrap(), &log_format, value, num_of_threads.try_into().unwrap());
+            packages::parser::parse_raw(input.input_fn.clone(), &input.config, value, num_of_threads)
         }
-        None => {
-            (double_dict, triple_dict, _all_token_list) = packages::parser::parse_raw(input_fn.unwrap(), &log_format, false, num_of_threads.try_into().unwrap());
+        (None, None) => {
+            packages::parser::parse_raw(input.input_fn.clone(), &input.config, false, num_of_threads)
         }
+    })
+}
+
+// --templates and everything built from the records of the raw log: time series, sessions,
+// count matrices, transitions, rate changes, parameter stats and co-occurrences
+fn analyses(input: &Input) -> io::Result<()> {
+    let args = input.args;
+    let session_key = match (&args.session_pattern, &args.session_field) {
+        (Some(p), _) => Some(packages::sessions::SessionKey::Pattern(regex::Regex::new(p).map_err(|_| invalid("bad --session-pattern"))?)),
+        (None, Some(f)) => Some(packages::sessions::SessionKey::Field(f.clone())),
+        (None, None) => None,
     };
-    view_double_and_triple_dicts(&double_dict, &triple_dict);
+    // a count matrix has a row per session if sessions are asked for, and per --bucket otherwise
+    let by_session = args.sessions.is_some() || ((args.count_matrix.is_some() || args.transitions.is_some()) && session_key.is_some());
+    let by_time = args.time_series.is_some() || args.rate_changes || (args.count_matrix.is_some() && session_key.is_none());
+    let needs_records = args.templates || by_time || by_session || args.transitions.is_some() || args.param_stats || args.cooccur.is_some();
+    if !needs_records {
+        return Ok(());
+    }
 
-    let (format_string_re, censored_regexps) =
-        (packages::parser::regex_generator(packages::parser::format_string(&log_format)), packages::parser::censored_regexps(&log_format));
+    // the raw log as the trained parser sees it, then --refine'd, once for all the analyses below,
+    // so that they agree on templates and template ids
+    let lines = input.read()?;
+    let mut records = input.trained_parser(&lines).parse(&lines);
+    // number the records as in the whole log, not the window
+    if let Some((line_nos, _)) = &input.in_window {
+        for (record, n) in records.iter_mut().zip(line_nos) {
+            record.line_no = *n;
+        }
+    }
+    if args.refine {
+        packages::refine::refine(&mut records, args.split_max, args.merge_min);
+    }
+    input.timestamps().attach(&mut records);
+
+    if args.templates {
+        packages::log_parser::print_templates(&records);
+    }
+
+    let series = by_time.then(|| packages::timeseries::time_series(&records, args.bucket));
+    if let Some(series_fn) = &args.time_series {
+        let series = series.as_ref().unwrap();
+        write_file(series_fn, |out| match args.wide {
+            true => packages::timeseries::write_wide(out, series),
+            false => packages::timeseries::write_long(out, series),
+        }).map_err(context("could not write time series"))?;
+    }
+
+    let sessions = by_session.then(|| packages::sessions::sessionize(&records, session_key.as_ref().unwrap(), &input.config.re));
+    if let Some(sessions_fn) = &args.sessions {
+        let sessions = sessions.as_ref().unwrap();
+        write_file(sessions_fn, |out| packages::sessions::write_sequences(out, sessions)).map_err(context("could not write sessions"))?;
+        println!("{} sessions, {} templates", sessions.sessions.len(), sessions.templates.len());
+    }
+
+    if let Some(matrix_fn) = &args.count_matrix {
+        let mut matrix = match &sessions {
+            Some(sessions) => packages::matrix::session_matrix(sessions),
+            None => packages::matrix::window_matrix(series.as_ref().unwrap()),
+        };
+        if args.tf_idf {
+            matrix.tf_idf();
+        }
+        write_file(matrix_fn, |out| match args.binary {
+            true => packages::matrix::write_binary(out, &matrix),
+            false => packages::matrix::write_csv(out, &matrix),
+        }).map_err(context("could not write count matrix"))?;
+        println!("{} x {} count matrix", matrix.values.len(), matrix.columns);
+    }
+
+    // without sessions, transitions follow line order
+    let graph = args.transitions.as_ref().map(|_| match &sessions {
+        Some(sessions) => packages::transitions::transitions(sessions),
+        None => packages::transitions::transitions(&packages::sessions::whole_log(&records)),
+    });
+    if let Some(dot_fn) = &args.transitions {
+        write_file(dot_fn, |out| packages::transitions::write_dot(out, graph.as_ref().unwrap(), args.min_edge)).map_err(context("could not write transition graph"))?;
+    }
+
+    if let Some(table_fn) = &args.template_table {
+        // the ids of sessions and matrices; without sessions, those of the transition graph or the time buckets
+        let templates = match (&sessions, &graph, &series) {
+            (Some(sessions), _, _) => &sessions.templates,
+            (None, Some(graph), _) => &graph.templates,
+            (None, None, Some(series)) => &series.templates,
+            (None, None, None) => unreachable!("clap makes --template-table require one of them"),
+        };
+        write_file(table_fn, |out| packages::sessions::write_template_table(out, templates)).map_err(context("could not write template table"))?;
+    }
+
+    if args.rate_changes {
+        let changes = packages::bursts::rate_changes(series.as_ref().unwrap(), args.alpha, args.min_history);
+        packages::bursts::print_rate_changes(&changes);
+    }
+
+    if args.param_stats {
+        packages::paramstats::print_parameter_stats(&packages::paramstats::parameter_stats(&records, args.top_values));
+    }
+
+    if let Some(target) = &args.cooccur {
+        let found = packages::cooccurrence::preceding(&records, target, &args.window).ok_or_else(|| invalid("no line has that template"))?;
+        packages::cooccurrence::print_cooccurrences(&found[..found.len().min(args.top)]);
+    }
+    Ok(())
+}
+
+fn compress(input: &Input, compressed_fn: &str) -> io::Result<()> {
+    let bytes = std::fs::read(&input.input_fn).map_err(context("could not read log"))?;
+    let compressed = packages::compress::compress(&bytes, &input.ngram_parser(), &input.config.re).map_err(context("could not compress log"))?;
+    std::fs::write(compressed_fn, &compressed).map_err(context("could not write compressed log"))?;
+    println!("compressed {} bytes to {} ({:.1}x)", bytes.len(), compressed.len(), bytes.len() as f64 / compressed.len() as f64);
+    Ok(())
+}
+
+fn index(input: &Input, index_fn: &str) -> io::Result<()> {
+    let bytes = std::fs::read(&input.input_fn).map_err(context("could not read log"))?;
+    let index = packages::index::build_index(&input.input_fn, &bytes, &input.ngram_parser()).map_err(context("could not index log"))?;
+    packages::index::save_index(index_fn, &index).map_err(context("could not write index"))?;
+    println!("indexed {} templates", index.templates.len());
+    Ok(())
+}
+
+fn benchmark(input: &Input) -> io::Result<()> {
+    let args = input.args;
+    let lines = input.read()?;
+    let truth = match &args.ground_truth {
+        Some(t) => Some(packages::benchmark::read_ground_truth(t).map_err(context("could not read ground truth"))?),
+        None => None,
+    };
+    let truth = truth.as_deref();
+    // every parser is trained from scratch and then parses the same lines, so that the timings are comparable
+    let results = vec![
+        packages::benchmark::run_benchmark("logram", &mut packages::log_parser::NgramParser::new(&input.config, input.cutoff()), &lines, truth),
+        packages::benchmark::run_benchmark("drain", &mut packages::drain::DrainParser::new(&input.config, args.drain_depth, args.drain_sim), &lines, truth),
+        packages::benchmark::run_benchmark("spell", &mut packages::spell::SpellParser::new(&input.config, args.spell_tau), &lines, truth),
+    ];
+    packages::benchmark::print_benchmark(&results);
+    Ok(())
+}
+
+fn detect(input: &Input, detect_fn: &str) -> io::Result<()> {
+    let known = packages::detect::known_templates(&input.read()?, &input.config, &input.double_dict, &input.triple_dict, input.cutoff());
+    let lines = packages::parser::read_log_lines(detect_fn).map_err(context("could not read log"))?;
+    let novelties = packages::detect::detect_novel(&lines, &input.config, &input.double_dict, &input.triple_dict, input.cutoff(), &known);
+    packages::detect::print_novelties(&novelties);
+    Ok(())
+}
+
+fn score(input: &Input, score_fn: &str) -> io::Result<()> {
+    let aggregate = match input.args.score_by.as_str() {
+        "min" => packages::score::Aggregate::Min,
+        _ => packages::score::Aggregate::Mean,
+    };
+    let vocab_size = input.all_token_list.iter().collect::<std::collections::HashSet<_>>().len();
+    let lines = packages::parser::read_log_lines(score_fn).map_err(context("could not read log"))?;
+    let scores = packages::score::score_lines(&lines, &input.config, &input.double_dict, &input.triple_dict, vocab_size, &aggregate);
+    packages::score::print_scores(&scores);
+    Ok(())
+}
+
+// --to-parse: the dynamic tokens of one line, with --before/--after or the lines around it as context
+fn parse_sample(input: &Input, to_parse: &str) {
+    let args = input.args;
+    let (config, cutoff) = (&input.config, input.cutoff());
+    let (double_dict, triple_dict) = (&input.double_dict, &input.triple_dict);
 
     //let sample_string = "Jun 23 23:30:05 combo sshd(pam_unix)[26190]: authentication failure; logname= uid=0 euid=0 tty=NODEVssh ruser= rhost=218.22.3.51  user=root authentication".to_string();
     // add befores and afters to the sample string, yielding extended_sample_string
-    let mut sample_string_tokens = packages::parser::token_splitter(args.to_parse, 
-                                                                    &format_string_re,
-                                                                    &censored_regexps);
-    let mut befores = match (args.before, args.before_line) {
+    let (mut sample_string_tokens, censored_values, sample_spans) = packages::parser::token_splitter_with_spans(to_parse.to_string(), config);
+    if !args.json {
+        if let Some(t) = input.timestamps().parse(to_parse) {
+            println!("timestamp: {}", packages::timestamp::format_timestamp(t));
+        }
+        for v in &censored_values {
+            println!("censored {} = {}", v.placeholder, v.value);
+        }
+    }
+    let mut befores = match (&args.before, &args.before_line) {
         (None, None) => vec![],
-        (Some(b), None) => b.split_whitespace().map(|s| s.to_string()).collect(),
+        (Some(b), None) => config.tokenizer.split(b),
         (None, Some(b)) | (Some(_), Some(b)) => {
-            let r = packages::parser::token_splitter(b,
-                                                     &format_string_re,
-                                                     &censored_regexps);
+            let r = packages::parser::token_splitter(b.clone(),
+                                                     config);
             r[r.len()-2..r.len()].to_vec()
         }
     };
-    let mut afters = match (args.after, args.after_line) {
+    let mut afters = match (&args.after, &args.after_line) {
         (None, None) => vec![],
-        (Some(a), None) => a.split_whitespace().map(|s| s.to_string()).collect(),
+        (Some(a), None) => config.tokenizer.split(a),
         (None, Some(a)) | (Some(_), Some(a)) => {
-            let r = packages::parser::token_splitter(a,
-                                                     &format_string_re,
-                                                     &censored_regexps);
+            let r = packages::parser::token_splitter(a.clone(),
+                                                     config);
             r[0..2].to_vec()
         }
     };
 
+    let (sample_start, sample_len) = (befores.len(), sample_string_tokens.len());
     let mut extended_sample_string_tokens = vec![];
     extended_sample_string_tokens.append(&mut befores);
     extended_sample_string_tokens.append(&mut sample_string_tokens);
     extended_sample_string_tokens.append(&mut afters);
-    println!("{:?}", extended_sample_string_tokens);
-
-    // collect 3-grams from extended_sample_string that occur less often than cutoff in the corpus
-    let mut uncommon_3grams = vec![];
-
-    for triple in extended_sample_string_tokens.windows(3) {
-        let three_gram = format!("{}^{}^{}", triple[0], triple[1], triple[2]);
-        if triple_dict.contains_key(&three_gram) && triple_dict.get(&three_gram).unwrap() < &cutoff {
-            // println!("3-gram {}, count {}", three_gram, &triple_dict.get(&three_gram).unwrap());
-            uncommon_3grams.push(three_gram);
+    if !args.json {
+        println!("{:?}", extended_sample_string_tokens);
+        for (two_gram, count) in packages::template::candidate_2grams(&extended_sample_string_tokens, double_dict, triple_dict, cutoff) {
+            println!("2-gram {}, count {}", two_gram, count);
         }
     }
 
-    let mut deduped_2grams_from_uncommon_3grams : HashSet<String> = HashSet::new();
-    for three_g in uncommon_3grams {
-        for two_g in derive_2grams_from_trigram(&three_g) {
-            deduped_2grams_from_uncommon_3grams.insert(two_g);
-        }
-    }
-    let mut uncommon_2grams : Vec<String> = vec![];
-    for two_g in deduped_2grams_from_uncommon_3grams {
-        let two_g_count = double_dict.get(&two_g).unwrap();
-        println!("2-gram {}, count {}", two_g, two_g_count);
-        if two_g_count < &cutoff {
-            uncommon_2grams.push(two_g);
-            // println!("2-gram {}, count {}", two_g, two_g_count);
-        }
-    }
-
-    // now, iterate on the original tokens again and look for uncommon 2grams that appear 
-    let mut dynamic_tokens = vec![];
-    for triple in extended_sample_string_tokens.windows(3) {
-        let two_gram1 = format!("{}^{}", triple[0], triple[1]);
-        let two_gram2 = format!("{}^{}", triple[1], triple[2]);
-        if uncommon_2grams.contains(&two_gram1) && uncommon_2grams.contains(&two_gram2) {
-            dynamic_tokens.push(triple[1].to_string());
-        }
-        // println!("focus is {}, have {} {}, contains is {}/{}", triple[1], two_gram1, two_gram2, uncommon_2grams.contains(&two_gram1), uncommon_2grams.contains(&two_gram2));
+    let dynamic_indices = packages::template::dynamic_token_indices(&extended_sample_string_tokens, double_dict, triple_dict, cutoff);
+    if args.json {
+        // only the line's own tokens have a place in it
+        let record = packages::template::Record {
+            line_no: 1,
+            raw: to_parse.to_string(),
+            tokens: extended_sample_string_tokens[sample_start..sample_start + sample_len].to_vec(),
+            dynamic: dynamic_indices.iter().filter(|i| **i >= sample_start && **i < sample_start + sample_len).map(|i| i - sample_start).collect(),
+            censored: censored_values,
+            spans: sample_spans,
+            timestamp: None,
+        };
+        println!("{}", packages::template::dynamic_tokens_json(&record.dynamic_tokens(), &record.raw));
+        return;
     }
+    let dynamic_tokens : Vec<String> = dynamic_indices
+        .into_iter()
+        .map(|i| extended_sample_string_tokens[i].clone())
+        .collect();
     println!("dynamic tokens: {:?}", dynamic_tokens);
+    let classes: Vec<&str> = dynamic_tokens.iter().map(|t| packages::classify::classify(t).name()).collect();
+    println!("dynamic token classes: {:?}", classes);
 }
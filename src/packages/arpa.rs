@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::packages::dictionary::Dictionaries;
+
+// Probabilities use absolute discounting with backoff: a seen n-gram gets (count - D) / count(context),
+// and the mass taken off is handed to the lower order through the context's backoff weight.
+// Contexts are counted as the sum of their continuations, which is what makes the counts
+// recoverable on import.
+const DISCOUNT: f64 = 0.5;
+
+// Written before \data\, where ARPA readers ignore it, so that import can undo the discount.
+const DISCOUNT_HEADER: &str = "logram discount:";
+
+fn split_key(key: &str, n: usize) -> Option<Vec<&str>> {
+    let parts: Vec<&str> = key.split('^').collect();
+    if parts.len() == n { Some(parts) } else { None }
+}
+
+struct Model {
+    unigrams: BTreeMap<String, f64>,
+    // context -> (continuation -> count)
+    bigrams: BTreeMap<String, BTreeMap<String, f64>>,
+    trigrams: BTreeMap<(String, String), BTreeMap<String, f64>>,
+}
+
+impl Model {
+    fn from_dictionaries(double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, all_token_list: &[String]) -> Model {
+        let mut unigrams: BTreeMap<String, f64> = all_token_list.iter().map(|t| (t.clone(), 0.0)).collect();
+        let mut right_marginals: HashMap<String, f64> = HashMap::new();
+        let mut bigrams: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+        for (key, count) in double_dict {
+            if let Some(w) = split_key(key, 2) {
+                bigrams.entry(w[0].to_string()).or_default().insert(w[1].to_string(), *count as f64);
+                *unigrams.entry(w[0].to_string()).or_default() += *count as f64;
+                *right_marginals.entry(w[1].to_string()).or_default() += *count as f64;
+            }
+        }
+        // tokens that never start a 2-gram (e.g. the very last one) are counted from the other side
+        for (w, c) in unigrams.iter_mut() {
+            if *c == 0.0 {
+                *c = right_marginals.get(w).cloned().unwrap_or(0.0).max(1.0);
+            }
+        }
+        let mut trigrams: BTreeMap<(String, String), BTreeMap<String, f64>> = BTreeMap::new();
+        for (key, count) in triple_dict {
+            if let Some(w) = split_key(key, 3) {
+                trigrams.entry((w[0].to_string(), w[1].to_string())).or_default().insert(w[2].to_string(), *count as f64);
+            }
+        }
+        Model { unigrams, bigrams, trigrams }
+    }
+
+    fn total(&self) -> f64 {
+        self.unigrams.values().sum()
+    }
+
+    fn p_unigram(&self, w: &str) -> f64 {
+        self.unigrams.get(w).cloned().unwrap_or(0.0) / self.total()
+    }
+
+    fn bigram_backoff(&self, w1: &str) -> f64 {
+        match self.bigrams.get(w1) {
+            None => 1.0,
+            Some(next) => backoff_weight(next, |w| self.p_unigram(w)),
+        }
+    }
+
+    fn p_bigram(&self, w1: &str, w2: &str) -> f64 {
+        match self.bigrams.get(w1).and_then(|next| next.get(w2).map(|c| (c, next))) {
+            Some((c, next)) => (c - DISCOUNT) / next.values().sum::<f64>(),
+            None => self.bigram_backoff(w1) * self.p_unigram(w2),
+        }
+    }
+}
+
+/// (1 - discounted mass of the seen continuations) / (1 - lower-order mass of the same continuations)
+fn backoff_weight<F: Fn(&str) -> f64>(next: &BTreeMap<String, f64>, p_lower: F) -> f64 {
+    let total: f64 = next.values().sum();
+    let seen: f64 = next.values().map(|c| (c - DISCOUNT) / total).sum();
+    let lower: f64 = next.keys().map(|w| p_lower(w)).sum();
+    (1.0 - seen) / (1.0 - lower).max(f64::EPSILON)
+}
+
+/// Writes the dictionaries as a 3-gram ARPA language model with log10 probabilities and
+/// backoff weights.
+pub fn export_arpa<P>(path: P, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, all_token_list: &[String]) -> io::Result<()>
+where P: AsRef<Path>, {
+    let model = Model::from_dictionaries(double_dict, triple_dict, all_token_list);
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{} {}", DISCOUNT_HEADER, DISCOUNT)?;
+    writeln!(out)?;
+    writeln!(out, "\\data\\")?;
+    writeln!(out, "ngram 1={}", model.unigrams.len())?;
+    writeln!(out, "ngram 2={}", model.bigrams.values().map(|n| n.len()).sum::<usize>())?;
+    writeln!(out, "ngram 3={}", model.trigrams.values().map(|n| n.len()).sum::<usize>())?;
+
+    // 12 decimals rather than the usual 6: recovering counts divides by 1 - sum(p), which is
+    // small for contexts with one frequent continuation
+    writeln!(out, "\n\\1-grams:")?;
+    for w in model.unigrams.keys() {
+        match model.bigrams.contains_key(w) {
+            true => writeln!(out, "{:.12}\t{}\t{:.12}", model.p_unigram(w).log10(), w, model.bigram_backoff(w).log10())?,
+            false => writeln!(out, "{:.12}\t{}", model.p_unigram(w).log10(), w)?,
+        }
+    }
+
+    writeln!(out, "\n\\2-grams:")?;
+    for (w1, next) in &model.bigrams {
+        for w2 in next.keys() {
+            let p = model.p_bigram(w1, w2).log10();
+            match model.trigrams.get(&(w1.clone(), w2.clone())) {
+                Some(next3) => writeln!(out, "{:.12}\t{} {}\t{:.12}", p, w1, w2, backoff_weight(next3, |w| model.p_bigram(w2, w)).log10())?,
+                None => writeln!(out, "{:.12}\t{} {}", p, w1, w2)?,
+            }
+        }
+    }
+
+    writeln!(out, "\n\\3-grams:")?;
+    for ((w1, w2), next) in &model.trigrams {
+        let total: f64 = next.values().sum();
+        for (w3, c) in next {
+            writeln!(out, "{:.12}\t{} {} {}", ((c - DISCOUNT) / total).log10(), w1, w2, w3)?;
+        }
+    }
+    writeln!(out, "\n\\end\\")?;
+    out.flush()
+}
+
+// Given the probabilities of all seen continuations of one context, absolute discounting gives
+// sum(p) = 1 - D * n / count(context), so the context count and each n-gram count follow.
+fn recover_counts(probs: &[(String, f64)], discount: f64) -> Vec<(String, i32)> {
+    let mass: f64 = probs.iter().map(|(_, p)| p).sum();
+    let context = discount * probs.len() as f64 / (1.0 - mass).max(f64::EPSILON);
+    probs.iter().map(|(k, p)| (k.clone(), (p * context + discount).round() as i32)).collect()
+}
+
+/// Reads an ARPA model back into logram dictionaries. Models written by `export_arpa` come
+/// back with their original counts; for other models the counts are the ones absolute
+/// discounting would have needed to produce the given probabilities.
+pub fn import_arpa<P>(path: P) -> io::Result<Dictionaries>
+where P: AsRef<Path>, {
+    let bad = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad ARPA line: {}", line));
+    let mut discount = DISCOUNT;
+    let mut order = 0;
+    let mut unigrams: Vec<(String, f64)> = vec![];
+    // context -> [(n-gram key, probability)]
+    let mut contexts: BTreeMap<(usize, String), Vec<(String, f64)>> = BTreeMap::new();
+
+    for line in io::BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(d) = line.strip_prefix(DISCOUNT_HEADER) {
+            discount = d.trim().parse::<f64>().map_err(|_| bad(line))?;
+        } else if let Some(n) = line.strip_prefix('\\').and_then(|l| l.strip_suffix("-grams:")) {
+            order = n.parse().map_err(|_| bad(line))?;
+        } else if line == "\\end\\" {
+            break;
+        } else if order > 0 && !line.is_empty() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < order + 1 {
+                return Err(bad(line));
+            }
+            let p = 10f64.powf(fields[0].parse::<f64>().map_err(|_| bad(line))?);
+            let words = &fields[1..order+1];
+            match order {
+                1 => unigrams.push((words[0].to_string(), p)),
+                2 | 3 => contexts.entry((order, words[..order-1].join("^"))).or_default().push((words.join("^"), p)),
+                _ => {} // logram only keeps 2- and 3-grams
+            }
+        }
+    }
+
+    let all_token_list = unigrams.iter().map(|(w, _)| w.clone()).collect();
+    let mut double_dict = HashMap::new();
+    let mut triple_dict = HashMap::new();
+    for ((n, _), probs) in contexts {
+        let d = if n == 2 { &mut double_dict } else { &mut triple_dict };
+        d.extend(recover_counts(&probs, discount));
+    }
+    println!("imported {} tokens, {} 2-grams, {} 3-grams", unigrams.len(), double_dict.len(), triple_dict.len());
+    Ok((double_dict, triple_dict, all_token_list))
+}
+
+#[test]
+fn test_arpa_round_trip() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::{dictionary_builder, FormatConfig};
+
+    let (double_dict, triple_dict, mut all_token_list) = dictionary_builder("data/Linux_2k.log".to_string(), FormatConfig::new(&Linux));
+    let path = std::env::temp_dir().join(format!("logram-{}.arpa", std::process::id()));
+    export_arpa(&path, &double_dict, &triple_dict, &all_token_list).unwrap();
+    let (dbl, trpl, tokens) = import_arpa(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    all_token_list.sort();
+    assert_eq!(tokens, all_token_list);
+    assert_eq!(dbl, double_dict);
+    assert_eq!(trpl, triple_dict);
+}
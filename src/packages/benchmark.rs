@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::packages::log_parser::LogParser;
+use crate::packages::parser::read_log_lines;
+use crate::packages::template::Record;
+
+pub struct BenchmarkResult {
+    pub parser: String,
+    pub train: Duration,
+    pub parse: Duration,
+    pub lines: usize,
+    pub templates: usize,
+    pub grouping_accuracy: Option<f64>,
+}
+
+/// Fraction of lines whose group (lines sharing a template) is exactly their ground-truth
+/// event's group, as in the Loghub parser benchmarks; None without lines.
+pub fn grouping_accuracy(records: &[Record], truth: &[String]) -> Option<f64> {
+    if records.is_empty() {
+        return None;
+    }
+    let mut truth_sizes: HashMap<&str, usize> = HashMap::new();
+    truth.iter().for_each(|t| *truth_sizes.entry(t).or_default() += 1);
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    records.iter().enumerate().for_each(|(i, r)| groups.entry(r.template()).or_default().push(i));
+
+    let mut correct = 0;
+    for lines in groups.values() {
+        let event = truth[lines[0]].as_str();
+        if lines.iter().all(|l| truth[*l] == event) && truth_sizes[event] == lines.len() {
+            correct += lines.len();
+        }
+    }
+    Some(correct as f64 / records.len() as f64)
+}
+
+// splits one CSV line, honouring double quotes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => { chars.next(); fields.last_mut().unwrap().push('"'); }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Reads ground-truth event ids: either the EventId column of a Loghub `*_structured.csv`,
+/// or a plain file with one id per log line.
+pub fn read_ground_truth<P>(path: P) -> io::Result<Vec<String>>
+where P: AsRef<Path>, {
+    let lines = read_log_lines(path)?;
+    let column = lines.first().and_then(|h| csv_fields(h).iter().position(|f| f == "EventId"));
+    Ok(match column {
+        None => lines,
+        Some(c) => lines[1..].iter().map(|l| csv_fields(l).get(c).cloned().unwrap_or_default()).collect(),
+    })
+}
+
+/// Trains `parser` on `lines` and then parses them, timing each. Every parser does the same
+/// work: one pass that learns templates and one that only looks them up.
+pub fn run_benchmark(name: &str, parser: &mut dyn LogParser, lines: &[String], truth: Option<&[String]>) -> BenchmarkResult {
+    let start_time = Instant::now();
+    parser.train(lines);
+    let train = start_time.elapsed();
+    let start_time = Instant::now();
+    let records = parser.parse(lines);
+    let parse = start_time.elapsed();
+
+    let mut templates: Vec<String> = records.iter().map(|r| r.template()).collect();
+    templates.sort();
+    templates.dedup();
+    let grouping_accuracy = match truth {
+        Some(t) if t.len() == records.len() => grouping_accuracy(&records, t),
+        Some(t) => {
+            println!("ground truth has {} lines but the log has {}, skipping accuracy", t.len(), records.len());
+            None
+        }
+        None => None,
+    };
+    BenchmarkResult { parser: name.to_string(), train, parse, lines: lines.len(), templates: templates.len(), grouping_accuracy }
+}
+
+pub fn print_benchmark(results: &[BenchmarkResult]) {
+    println!("{:<8} {:>12} {:>12} {:>14} {:>10} {:>10}", "parser", "train", "parse", "lines/sec", "templates", "accuracy");
+    for r in results {
+        let accuracy = r.grouping_accuracy.map_or("-".to_string(), |a| format!("{:.4}", a));
+        // too fast to time, say for an empty log
+        let elapsed = (r.train + r.parse).as_secs_f64();
+        let throughput = if elapsed > 0.0 { format!("{:.0}", r.lines as f64 / elapsed) } else { "-".to_string() };
+        println!("{:<8} {:>12?} {:>12?} {:>14} {:>10} {:>10}", r.parser, r.train, r.parse, throughput, r.templates, accuracy);
+    }
+}
+
+#[test]
+fn test_grouping_accuracy() {
+    let record = |template: &str| Record { line_no: 0, raw: String::new(), tokens: template.split_whitespace().map(|t| t.to_string()).collect(), dynamic: vec![], censored: vec![], spans: vec![], timestamp: None };
+    let records = vec![record("a"), record("a"), record("b"), record("c"), record("c")];
+    let truth: Vec<String> = ["1", "1", "2", "3", "4"].iter().map(|s| s.to_string()).collect();
+    // "a" and "b" are grouped right, "c" merges two events
+    assert_eq!(grouping_accuracy(&records, &truth), Some(0.6));
+    assert_eq!(grouping_accuracy(&[], &[]), None);
+
+    assert_eq!(csv_fields(r#"1,"a, ""b""",E5"#), vec!["1", r#"a, "b""#, "E5"]);
+}
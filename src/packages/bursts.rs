@@ -0,0 +1,137 @@
+use crate::packages::timeseries::TimeSeries;
+use crate::packages::timestamp::format_timestamp;
+
+// Flags buckets where a template occurs far more or far less often than it did before. A
+// template's expected count in a bucket is its mean count over the earlier buckets since it
+// first occurred, and the observed count is tested against a Poisson distribution with that
+// mean. Templates that have not occurred before have no rate to compare with; --detect is for
+// those.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// many more lines than expected in one bucket
+    Burst,
+    /// many fewer lines than expected in one bucket
+    Drop,
+    /// no lines at all from some bucket to the end of the log, where some were expected
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateChange {
+    pub change: Change,
+    pub template: String,
+    /// start of the first bucket and end of the last
+    pub start: i64,
+    pub end: i64,
+    pub expected: f64,
+    pub observed: usize,
+    /// probability of a count at least this far from the expected one
+    pub p: f64,
+}
+
+fn ln_factorial(n: usize) -> f64 {
+    if n < 20 {
+        return (2..=n).map(|i| (i as f64).ln()).sum();
+    }
+    // Stirling's series
+    let x = n as f64;
+    x * x.ln() - x + 0.5 * (2.0 * std::f64::consts::PI * x).ln() + 1.0 / (12.0 * x) - 1.0 / (360.0 * x.powi(3))
+}
+
+fn poisson(i: usize, mean: f64) -> f64 {
+    (i as f64 * mean.ln() - mean - ln_factorial(i)).exp()
+}
+
+/// P(X <= k) for X Poisson with `mean`.
+fn lower_tail(k: usize, mean: f64) -> f64 {
+    (0..=k).map(|i| poisson(i, mean)).sum::<f64>().min(1.0)
+}
+
+/// P(X >= k) for X Poisson with `mean`, summed directly so that tiny tails keep their precision.
+fn upper_tail(k: usize, mean: f64) -> f64 {
+    let mut sum = 0.0;
+    for i in k.. {
+        let term = poisson(i, mean);
+        sum += term;
+        if i as f64 > mean && term <= sum * 1e-12 {
+            break;
+        }
+    }
+    sum.min(1.0)
+}
+
+/// The bursts, drops and stops in `series` whose probability is below `alpha`, in time order.
+/// A template is only tested `min_history` buckets after it first occurred, so that its mean
+/// means something.
+pub fn rate_changes(series: &TimeSeries, alpha: f64, min_history: usize) -> Vec<RateChange> {
+    let buckets = series.buckets();
+    let mut changes = vec![];
+    for (t, template) in series.templates.iter().enumerate() {
+        let counts: Vec<usize> = buckets.iter().map(|b| series.count(*b, t)).collect();
+        let first_seen = counts.iter().position(|c| *c > 0).unwrap();
+        let last_seen = counts.iter().rposition(|c| *c > 0).unwrap();
+        let mut total = 0;
+        for (i, observed) in counts.iter().copied().enumerate().skip(first_seen) {
+            let expected = total as f64 / (i - first_seen) as f64;
+            total += observed;
+            // every empty bucket after the last line would be a drop; they make one stop instead
+            if i - first_seen < min_history.max(1) || i > last_seen {
+                continue;
+            }
+            let (change, p) = match observed as f64 > expected {
+                true => (Change::Burst, upper_tail(observed, expected)),
+                false => (Change::Drop, lower_tail(observed, expected)),
+            };
+            if p < alpha {
+                changes.push(RateChange { change, template: template.clone(), start: buckets[i], end: buckets[i] + series.bucket, expected, observed, p });
+            }
+        }
+
+        let silent = buckets.len() - 1 - last_seen;
+        if last_seen - first_seen + 1 >= min_history && silent > 0 {
+            let expected = total as f64 / (last_seen - first_seen + 1) as f64 * silent as f64;
+            let p = (-expected).exp();
+            if p < alpha {
+                let (start, end) = (buckets[last_seen + 1], buckets[buckets.len() - 1] + series.bucket);
+                changes.push(RateChange { change: Change::Stopped, template: template.clone(), start, end, expected, observed: 0, p });
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.start.cmp(&b.start).then(a.p.total_cmp(&b.p)));
+    changes
+}
+
+pub fn print_rate_changes(changes: &[RateChange]) {
+    println!("{} rate changes", changes.len());
+    for c in changes {
+        println!("{:?} {} to {}: expected {:.1}, observed {} (p = {:.2e}): {}",
+                 c.change, format_timestamp(c.start), format_timestamp(c.end), c.expected, c.observed, c.p, c.template);
+    }
+}
+
+#[test]
+fn test_poisson_tails() {
+    assert!((lower_tail(0, 2.0) - (-2.0f64).exp()).abs() < 1e-12);
+    assert!((lower_tail(3, 2.0) + upper_tail(4, 2.0) - 1.0).abs() < 1e-9);
+    assert!((lower_tail(100, 100.0) - 0.5266).abs() < 1e-3);
+    assert!(upper_tail(60, 10.0) < 1e-20);
+}
+
+#[test]
+fn test_rate_changes() {
+    use std::collections::BTreeMap;
+
+    // "steady" occurs 5 times a minute, bursts to 40 in minute 6 and stops after minute 9;
+    // "quiet" goes from 10 a minute to 0 in minute 7 and then comes back
+    let steady = [5, 4, 6, 5, 5, 6, 40, 5, 4, 5, 0, 0, 0, 0, 0, 0];
+    let quiet = [10, 9, 11, 10, 10, 9, 10, 0, 10, 11, 10, 9, 10, 11, 10, 9];
+    let counts: BTreeMap<i64, Vec<usize>> = (0..16).map(|i| (i as i64 * 60_000, vec![steady[i], quiet[i]])).collect();
+    let series = TimeSeries { bucket: 60_000, templates: vec!["steady".to_string(), "quiet".to_string()], counts };
+
+    let changes = rate_changes(&series, 0.001, 5);
+    let found: Vec<(Change, &str, i64, usize)> = changes.iter().map(|c| (c.change, c.template.as_str(), c.start / 60_000, c.observed)).collect();
+    assert_eq!(found, vec![(Change::Burst, "steady", 6, 40), (Change::Drop, "quiet", 7, 0), (Change::Stopped, "steady", 10, 0)]);
+    assert_eq!(changes[2].end, 16 * 60_000);
+    assert!((changes[0].expected - 31.0 / 6.0).abs() < 1e-9);
+}
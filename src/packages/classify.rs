@@ -0,0 +1,68 @@
+use std::sync::OnceLock;
+use regex::Regex;
+
+// Classifies whole tokens by shape. Deciding about a whole token sidesteps the lookbehind a
+// regexp over the line would need to tell the 42 in "size 42" from the one in "blk42".
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Integer,
+    Float,
+    Hex,
+    Version,
+    /// letters and digits mixed, e.g. blk_123 or user42
+    Id,
+    Word,
+}
+
+use TokenClass::*;
+
+/// Classes in the order they are tried; the first match wins.
+const CLASSES: [TokenClass; 5] = [Integer, Float, Hex, Version, Id];
+
+impl TokenClass {
+    /// Name of the class's placeholder, without the brackets.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integer => "INT",
+            Float => "FLOAT",
+            Hex => "HEX",
+            Version => "VERSION",
+            Id => "ID",
+            Word => "WORD",
+        }
+    }
+
+    fn shape(&self) -> &'static str {
+        match self {
+            Integer => r"[-+]?\d+",
+            Float => r"[-+]?(\d+\.\d*|\.\d+)([eE][-+]?\d+)?|[-+]?\d+[eE][-+]?\d+",
+            // a 0x prefix, or hex digits with at least one decimal digit (so "cafe" is a word)
+            Hex => r"0[xX][0-9a-fA-F]+|[a-fA-F]*\d[0-9a-fA-F]*",
+            Version => r"[vV]?\d+(\.\d+){2,}([-+][\w.]+)?|[vV]\d+(\.\d+)*",
+            Id => r"[\w.:-]*\d[\w.:-]*",
+            Word => r"\S+",
+        }
+    }
+}
+
+// A regexp matching exactly the tokens of `class`.
+fn class_regex(class: TokenClass) -> Regex {
+    Regex::new(&format!(r"^(?P<{}>{})$", class.name(), class.shape())).unwrap()
+}
+
+/// The class of a single token.
+pub fn classify(token: &str) -> TokenClass {
+    static REGEXPS: OnceLock<Vec<(TokenClass, Regex)>> = OnceLock::new();
+    let regexps = REGEXPS.get_or_init(|| CLASSES.iter().map(|c| (*c, class_regex(*c))).collect());
+    regexps.iter().find(|(_, re)| re.is_match(token)).map_or(Word, |(c, _)| *c)
+}
+
+#[test]
+fn test_classify() {
+    for (token, class) in [("42", Integer), ("-7", Integer), ("3.14", Float), ("1e-3", Float), (".5", Float),
+                           ("0x1F", Hex), ("7fff5fbff8a0", Hex), ("cafe", Word), ("1.2.3", Version), ("v2", Version),
+                           ("2.4.1-rc1", Version), ("blk_-123", Id), ("user42", Id), ("pass;", Word), ("<IP>", Word)] {
+        assert_eq!(classify(token), class, "{}", token);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use regex::Regex;
+
+use crate::packages::classify::{classify, TokenClass};
+use crate::packages::log_parser::LogParser;
+use crate::packages::structured::{raw_lines, reconstruct, structure, RawLine};
+
+// Compressed layout, after the magic bytes, all deflated together:
+//   line count, then the indices of Latin-1 lines (delta-encoded)
+//   template table: each template's literal segments
+//   template id of every line
+//   for every template, one column per parameter slot, holding that slot's values in line order
+// Keeping a slot's values together puts similar text next to each other for deflate, and lets
+// numeric columns be stored as differences between consecutive values, which are small for
+// timestamps, PIDs and counters.
+// Numbers are LEB128 varints; signed ones are zigzag-encoded first.
+
+const MAGIC: &[u8] = b"LOGRAMZ1";
+
+// column kinds
+const STRINGS: u8 = 0;
+// every value a plain integer: deltas
+const INTEGERS: u8 = 1;
+// every value the same text with fixed-width digit runs in the same places, e.g. 02:04:59:
+// the text between runs once, then one delta-encoded column per run
+const DIGIT_RUNS: u8 = 2;
+
+// few distinct values: each distinct value once, then each value's index among them
+const DISTINCT: u8 = 3;
+
+// digit runs longer than this may not fit an i64
+const MAX_RUN: usize = 18;
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_signed(out: &mut Vec<u8>, n: i64) {
+    write_varint(out, ((n << 1) ^ (n >> 63)) as u64);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_deltas(out: &mut Vec<u8>, values: &[i64]) {
+    let mut prev = 0i64;
+    for v in values {
+        write_signed(out, v.wrapping_sub(prev));
+        prev = *v;
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt compressed log")
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let b = *self.data.get(self.pos).ok_or_else(corrupt)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(corrupt())
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        Ok(self.varint()? as usize)
+    }
+
+    fn signed(&mut self) -> io::Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.len()?;
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(corrupt)?;
+        self.pos += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt())
+    }
+
+    fn deltas(&mut self, n: usize) -> io::Result<Vec<i64>> {
+        let mut prev = 0i64;
+        (0..n).map(|_| {
+            prev = prev.wrapping_add(self.signed()?);
+            Ok(prev)
+        }).collect()
+    }
+}
+
+// an integer that prints back the same: no leading zeros or plus sign
+fn plain_integer(s: &str) -> Option<i64> {
+    let n: i64 = s.parse().ok()?;
+    if n.to_string() == s { Some(n) } else { None }
+}
+
+// the text around the digit runs of `s`, and each run
+fn digit_runs(s: &str) -> (Vec<&str>, Vec<&str>) {
+    let (mut texts, mut runs) = (vec![], vec![]);
+    let mut last = 0;
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i].is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            texts.push(&s[last..start]);
+            runs.push(&s[start..i]);
+            last = i;
+        } else {
+            i += 1;
+        }
+    }
+    texts.push(&s[last..]);
+    (texts, runs)
+}
+
+fn write_column(out: &mut Vec<u8>, values: &[&str]) {
+    if let Some(ints) = values.iter().map(|v| plain_integer(v)).collect::<Option<Vec<i64>>>() {
+        out.push(INTEGERS);
+        write_deltas(out, &ints);
+        return;
+    }
+
+    let (texts, runs) = digit_runs(values[0]);
+    let widths: Vec<usize> = runs.iter().map(|r| r.len()).collect();
+    let same_shape = !runs.is_empty() && widths.iter().all(|w| *w <= MAX_RUN) && values.iter().all(|v| {
+        let (t, r) = digit_runs(v);
+        t == texts && r.iter().map(|x| x.len()).eq(widths.iter().cloned())
+    });
+    if same_shape {
+        out.push(DIGIT_RUNS);
+        write_varint(out, runs.len() as u64);
+        for (text, width) in texts.iter().zip(widths.iter().chain(std::iter::once(&0))) {
+            write_str(out, text);
+            write_varint(out, *width as u64);
+        }
+        for k in 0..runs.len() {
+            let column: Vec<i64> = values.iter().map(|v| digit_runs(v).1[k].parse().unwrap()).collect();
+            write_deltas(out, &column);
+        }
+        return;
+    }
+
+    let mut distinct: HashMap<&str, usize> = HashMap::new();
+    let mut table = vec![];
+    let indices: Vec<usize> = values.iter().map(|v| *distinct.entry(v).or_insert_with(|| {
+        table.push(*v);
+        table.len() - 1
+    })).collect();
+    if table.len() * 2 <= values.len() {
+        out.push(DISTINCT);
+        write_varint(out, table.len() as u64);
+        for v in table {
+            write_str(out, v);
+        }
+        for i in indices {
+            write_varint(out, i as u64);
+        }
+        return;
+    }
+
+    out.push(STRINGS);
+    for v in values {
+        write_str(out, v);
+    }
+}
+
+fn read_column(reader: &mut Reader, n: usize) -> io::Result<Vec<String>> {
+    match reader.byte()? {
+        INTEGERS => Ok(reader.deltas(n)?.iter().map(|v| v.to_string()).collect()),
+        DIGIT_RUNS => {
+            let n_runs = reader.len()?;
+            let mut shape = vec![];
+            for _ in 0..=n_runs {
+                shape.push((reader.string()?, reader.len()?));
+            }
+            let runs: Vec<Vec<i64>> = (0..n_runs).map(|_| reader.deltas(n)).collect::<io::Result<_>>()?;
+            Ok((0..n).map(|i| {
+                let mut value = String::new();
+                for (k, (text, width)) in shape.iter().enumerate() {
+                    value.push_str(text);
+                    if k < n_runs {
+                        value.push_str(&format!("{:0width$}", runs[k][i], width = width));
+                    }
+                }
+                value
+            }).collect())
+        }
+        DISTINCT => {
+            let table: Vec<String> = (0..reader.len()?).map(|_| reader.string()).collect::<io::Result<_>>()?;
+            (0..n).map(|_| table.get(reader.len()?).cloned().ok_or_else(corrupt)).collect()
+        }
+        STRINGS => (0..n).map(|_| reader.string()).collect(),
+        _ => Err(corrupt()),
+    }
+}
+
+/// Compresses the bytes of a log. `parser` decides the templates and `re` is the format
+/// regexp; neither is needed to decompress.
+pub fn compress(bytes: &[u8], parser: &dyn LogParser, re: &Regex) -> io::Result<Vec<u8>> {
+    let lines = raw_lines(bytes);
+    let texts: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+    let mut records = parser.parse(&texts);
+    // numbers the parser kept static would otherwise give every value its own template
+    for r in records.iter_mut() {
+        r.dynamic = (0..r.tokens.len()).filter(|i| r.dynamic.contains(i) || classify(&r.tokens[*i]) != TokenClass::Word).collect();
+    }
+    let structured: Vec<_> = records.iter().map(|r| structure(r, re)).collect();
+
+    let mut template_ids: HashMap<&Vec<String>, usize> = HashMap::new();
+    let mut templates: Vec<&Vec<String>> = vec![];
+    let mut ids = vec![];
+    for s in &structured {
+        let id = *template_ids.entry(&s.segments).or_insert_with(|| {
+            templates.push(&s.segments);
+            templates.len() - 1
+        });
+        ids.push(id);
+    }
+
+    let mut out = vec![];
+    write_varint(&mut out, lines.len() as u64);
+    let latin1: Vec<i64> = lines.iter().enumerate().filter(|(_, l)| l.latin1).map(|(i, _)| i as i64).collect();
+    write_varint(&mut out, latin1.len() as u64);
+    write_deltas(&mut out, &latin1);
+    write_varint(&mut out, templates.len() as u64);
+    for segments in &templates {
+        write_varint(&mut out, segments.len() as u64);
+        for segment in segments.iter() {
+            write_str(&mut out, segment);
+        }
+    }
+    for id in &ids {
+        write_varint(&mut out, *id as u64);
+    }
+    for (t, segments) in templates.iter().enumerate() {
+        let rows: Vec<&Vec<String>> = structured.iter().zip(&ids).filter(|(_, id)| **id == t).map(|(s, _)| &s.params).collect();
+        for slot in 0..segments.len() - 1 {
+            let column: Vec<&str> = rows.iter().map(|params| params[slot].as_str()).collect();
+            write_column(&mut out, &column);
+        }
+    }
+
+    let mut encoder = DeflateEncoder::new(MAGIC.to_vec(), Compression::best());
+    encoder.write_all(&out)?;
+    encoder.finish()
+}
+
+/// Gives back the exact bytes `compress` was given.
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let body = data.strip_prefix(MAGIC).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a compressed log"))?;
+    let mut inflated = vec![];
+    DeflateDecoder::new(body).read_to_end(&mut inflated)?;
+    let mut reader = Reader { data: &inflated, pos: 0 };
+
+    let n_lines = reader.len()?;
+    let n_latin1 = reader.len()?;
+    let latin1 = reader.deltas(n_latin1)?;
+    let n_templates = reader.len()?;
+    let mut templates = vec![];
+    for _ in 0..n_templates {
+        let n_segments = reader.len()?;
+        templates.push((0..n_segments).map(|_| reader.string()).collect::<io::Result<Vec<String>>>()?);
+    }
+    let ids: Vec<usize> = (0..n_lines).map(|_| reader.len()).collect::<io::Result<_>>()?;
+    if ids.iter().any(|id| *id >= n_templates) || templates.iter().any(|t| t.is_empty()) {
+        return Err(corrupt());
+    }
+    let mut counts = vec![0; n_templates];
+    ids.iter().for_each(|id| counts[*id] += 1);
+    // columns[template][slot], consumed front to back
+    let mut columns: Vec<Vec<std::vec::IntoIter<String>>> = vec![];
+    for (t, segments) in templates.iter().enumerate() {
+        columns.push((0..segments.len() - 1).map(|_| read_column(&mut reader, counts[t]).map(|c| c.into_iter())).collect::<io::Result<_>>()?);
+    }
+
+    let mut lines = vec![];
+    for (i, id) in ids.iter().enumerate() {
+        let params: Vec<String> = columns[*id].iter_mut().map(|c| c.next().unwrap()).collect();
+        let text = reconstruct(&templates[*id], &params);
+        lines.push(RawLine { text, latin1: latin1.binary_search(&(i as i64)).is_ok() }.to_bytes());
+    }
+    Ok(lines.join(&b'\n'))
+}
+
+#[cfg(test)]
+fn gzip_size(bytes: &[u8]) -> usize {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap().len()
+}
+
+#[test]
+fn test_compress_round_trip() {
+    use crate::LogFormat::{Linux, Proxifier};
+    use crate::packages::log_parser::NgramParser;
+    use crate::packages::parser::{format_string, regex_generator, FormatConfig};
+
+    for (path, lf) in [("data/Proxifier_2k.log", Proxifier), ("data/Linux_2k.log", Linux)] {
+        let mut bytes = std::fs::read(path).unwrap();
+        // a line that is not UTF-8, and a trailing newline
+        bytes.extend_from_slice(b"\nJun 15 02:04:59 combo kernel: bad \xf7 byte\n");
+        let texts: Vec<String> = raw_lines(&bytes).into_iter().map(|l| l.text).collect();
+        let mut parser = NgramParser::new(&FormatConfig::new(&lf), 100);
+        parser.train(&texts);
+        let compressed = compress(&bytes, &parser, &regex_generator(format_string(&lf))).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), bytes);
+        assert!(compressed.len() < gzip_size(&bytes), "{}: {} vs gzip {}", path, compressed.len(), gzip_size(&bytes));
+    }
+}
+
+#[test]
+fn test_columns() {
+    let mut out = vec![];
+    let columns = [vec!["17", "-3", "1000"], vec!["02:04:59", "02:05:01", "23:59:59"], vec!["07", "7", "x"], vec!["a1", "b2", "c3"], vec!["x", "y", "x", "x"]];
+    for column in &columns {
+        write_column(&mut out, column);
+    }
+    assert_eq!(out[0], INTEGERS);
+    let mut reader = Reader { data: &out, pos: 0 };
+    for column in &columns {
+        assert_eq!(read_column(&mut reader, column.len()).unwrap(), *column);
+    }
+    assert_eq!(reader.pos, out.len());
+}
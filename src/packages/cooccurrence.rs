@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use crate::packages::sessions::whole_log;
+use crate::packages::template::Record;
+
+// Which templates tend to come shortly before a given one, e.g. before an error. Every line
+// has a window of lines before it; a template's confidence is the fraction of the target's
+// windows it occurs in, and its lift divides that by the fraction of all lines' windows it
+// occurs in, so that templates that are everywhere do not stand out.
+
+/// How far back from a line to look.
+#[derive(Clone, Debug)]
+pub enum Window {
+    /// this many lines
+    Lines(usize),
+    /// this many milliseconds, using the records' timestamps
+    Time(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cooccurrence {
+    pub template: String,
+    /// windows of the target that the template occurs in
+    pub count: usize,
+    /// that count as a fraction of all windows
+    pub support: f64,
+    /// that count as a fraction of the target's windows
+    pub confidence: f64,
+    pub lift: f64,
+}
+
+/// Reads a --window: a number of lines, or a duration such as 5m.
+pub fn parse_window(s: &str) -> Option<Window> {
+    match s.parse::<usize>() {
+        Ok(n) => Some(Window::Lines(n)),
+        Err(_) => crate::packages::timestamp::parse_duration(s).map(Window::Time),
+    }
+}
+
+/// The templates in the windows before lines of `target` (a template, or its id among the
+/// templates in order of first appearance, the ids `sessions` and `time_series` give the same
+/// records), most frequent first. Time windows need the
+/// records' timestamps attached; a record without one takes the previous record's, and
+/// records before the first timestamp are left out. None if no line has the target template.
+pub fn preceding(records: &[Record], target: &str, window: &Window) -> Option<Vec<Cooccurrence>> {
+    let log = whole_log(records);
+    let mut sequence = log.sessions.first().map(|(_, s)| s.clone()).unwrap_or_default();
+    let mut times = vec![];
+    let mut last = None;
+    for record in records.iter().filter(|r| !r.tokens.is_empty()) {
+        last = record.timestamp.or(last);
+        times.push(last);
+    }
+    if let Window::Time(_) = window {
+        let untimed = times.iter().take_while(|t| t.is_none()).count();
+        sequence.drain(..untimed);
+        times.drain(..untimed);
+    }
+    let target = match target.parse::<usize>() {
+        Ok(id) if id < log.templates.len() => id,
+        _ => log.templates.iter().position(|t| t == target)?,
+    };
+
+    // counts of each template in the window [lo, i) before line i
+    let mut in_window: HashMap<usize, usize> = HashMap::new();
+    let mut everywhere: HashMap<usize, usize> = HashMap::new();
+    let mut before_target: HashMap<usize, usize> = HashMap::new();
+    let mut targets = 0;
+    let mut lo = 0;
+    for i in 0..sequence.len() {
+        if i > 0 {
+            *in_window.entry(sequence[i - 1]).or_insert(0) += 1;
+        }
+        let start = match window {
+            Window::Lines(n) => i.saturating_sub(*n),
+            Window::Time(ms) => {
+                let mut start = lo;
+                while start < i && times[start].unwrap() < times[i].unwrap() - ms {
+                    start += 1;
+                }
+                start
+            }
+        };
+        for t in &sequence[lo..start.max(lo)] {
+            let c = in_window.get_mut(t).unwrap();
+            *c -= 1;
+            if *c == 0 {
+                in_window.remove(t);
+            }
+        }
+        lo = start.max(lo);
+
+        for t in in_window.keys() {
+            *everywhere.entry(*t).or_insert(0) += 1;
+        }
+        if sequence[i] == target {
+            targets += 1;
+            for t in in_window.keys() {
+                *before_target.entry(*t).or_insert(0) += 1;
+            }
+        }
+    }
+
+    if targets == 0 {
+        return None;
+    }
+    let n = sequence.len() as f64;
+    let mut found: Vec<Cooccurrence> = before_target.into_iter().map(|(t, count)| {
+        let confidence = count as f64 / targets as f64;
+        Cooccurrence { template: log.templates[t].clone(), count, support: count as f64 / n, confidence, lift: confidence / (everywhere[&t] as f64 / n) }
+    }).collect();
+    found.sort_by(|a, b| b.count.cmp(&a.count).then(b.lift.total_cmp(&a.lift)).then(a.template.cmp(&b.template)));
+    Some(found)
+}
+
+pub fn print_cooccurrences(found: &[Cooccurrence]) {
+    for c in found {
+        println!("{} ({:.0}% of windows), support {:.4}, lift {:.2}: {}", c.count, c.confidence * 100.0, c.support, c.lift, c.template);
+    }
+}
+
+#[test]
+fn test_preceding() {
+    let record = |template: &str, seconds: i64| Record {
+        line_no: 0, raw: String::new(), tokens: vec![template.to_string()],
+        dynamic: vec![], censored: vec![], spans: vec![], timestamp: Some(seconds * 1000),
+    };
+    // "disk" comes right before every "error"; "tick" is everywhere
+    let lines = [("tick", 0), ("disk", 1), ("error", 2), ("tick", 10), ("tick", 20), ("tick", 30), ("disk", 40), ("error", 41), ("tick", 50)];
+    let records: Vec<Record> = lines.iter().map(|(t, s)| record(t, *s)).collect();
+
+    let found = preceding(&records, "error", &Window::Lines(1)).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].template.as_str(), found[0].count, found[0].confidence), ("disk", 2, 1.0));
+    assert!((found[0].lift - 9.0 / 2.0).abs() < 1e-9);
+
+    let found = preceding(&records, "2", &Window::Time(5000)).unwrap();
+    assert_eq!(found.iter().map(|c| (c.template.as_str(), c.count)).collect::<Vec<_>>(), vec![("disk", 2), ("tick", 1)]);
+    assert!(preceding(&records, "nothing", &Window::Lines(3)).is_none());
+}
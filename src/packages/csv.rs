@@ -0,0 +1,19 @@
+// Just enough CSV to write tables without pulling in a writer.
+
+/// `s` as a CSV field, quoted if it holds a comma, quote or line break.
+pub fn field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+/// One CSV line, without its line break.
+pub fn row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter().map(|f| field(f.as_ref())).collect::<Vec<_>>().join(",")
+}
+
+#[test]
+fn test_row() {
+    assert_eq!(row(&["1", "a, \"b\"", "c"]), r#"1,"a, ""b""",c"#);
+}
@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::packages::parser::{header_time, FormatConfig};
+use crate::packages::template::{extract_records, Record};
+
+/// A scanned line that looks like nothing in the training log.
+#[derive(Debug)]
+pub struct Novelty {
+    pub record: Record,
+    pub template: String,
+    pub new_template: bool,
+    pub novel_3grams: Vec<String>,
+    // header time of the first scanned line with this template
+    pub first_seen: Option<String>,
+}
+
+/// Flags lines of `lines` whose 3-grams are all missing from the training `triple_dict`, or
+/// whose template is not one of `known_templates`. Templates are extracted with the training
+/// dictionaries, so the two sides are comparable.
+pub fn detect_novel(lines: &[String], config: &FormatConfig, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, cutoff: i32, known_templates: &HashSet<String>) -> Vec<Novelty> {
+    let mut first_seen: HashMap<String, Option<String>> = HashMap::new();
+    let mut novelties = vec![];
+    for record in extract_records(lines, config, double_dict, triple_dict, cutoff) {
+        if record.tokens.is_empty() {
+            continue;
+        }
+        let template = record.template();
+        let seen = first_seen.entry(template.clone()).or_insert_with(|| header_time(&record.raw, &config.re)).clone();
+
+        let three_grams: Vec<String> = record.tokens.windows(3).map(|t| format!("{}^{}^{}", t[0], t[1], t[2])).collect();
+        let novel_3grams: Vec<String> = three_grams.iter().filter(|g| !triple_dict.contains_key(*g)).cloned().collect();
+        let all_novel = !three_grams.is_empty() && novel_3grams.len() == three_grams.len();
+        let new_template = !known_templates.contains(&template);
+        if all_novel || new_template {
+            novelties.push(Novelty { record, template, new_template, novel_3grams, first_seen: seen });
+        }
+    }
+    novelties
+}
+
+/// Templates of every line in the training log.
+pub fn known_templates(lines: &[String], config: &FormatConfig, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, cutoff: i32) -> HashSet<String> {
+    extract_records(lines, config, double_dict, triple_dict, cutoff).iter().map(|r| r.template()).collect()
+}
+
+pub fn print_novelties(novelties: &[Novelty]) {
+    println!("{} novel lines", novelties.len());
+    for n in novelties {
+        println!("line {} (first seen {}): {}", n.record.line_no, n.first_seen.as_deref().unwrap_or("?"), n.record.raw);
+        if n.new_template {
+            println!("    new template: {}", n.template);
+        }
+        println!("    novel 3-grams: {:?}", n.novel_3grams);
+    }
+}
+
+#[test]
+fn test_detect_novel() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::dictionary_builder;
+
+    let config = FormatConfig::new(&Linux);
+    let (double_dict, triple_dict, _) = dictionary_builder("data/Linux_2k.log".to_string(), config.clone());
+    let training: Vec<String> = std::fs::read_to_string("data/Linux_2k.log").unwrap().lines().map(|l| l.to_string()).collect();
+    let known = known_templates(&training, &config, &double_dict, &triple_dict, 100);
+
+    let scanned = vec![
+        training[1].clone(),
+        "Jul 28 10:00:00 combo kernel: flux capacitor overheated badly".to_string(),
+        training[2].clone(),
+    ];
+    let novelties = detect_novel(&scanned, &config, &double_dict, &triple_dict, 100, &known);
+    assert_eq!(novelties.len(), 1);
+    assert_eq!(novelties[0].record.line_no, 2);
+    assert_eq!(novelties[0].novel_3grams.len(), 2);
+    assert_eq!(novelties[0].first_seen, Some("Jul 28 10:00:00".to_string()));
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+/// (double_dict, triple_dict, all_token_list), as returned by `parse_raw`.
+pub type Dictionaries = (HashMap<String, i32>, HashMap<String, i32>, Vec<String>);
+
+// On-disk dictionary format, one entry per line:
+//   double <count> <2-gram>
+//   triple <count> <3-gram>
+//   token <token>
+// Tokens and n-grams are escaped, so that a space, line break or backslash in a token (say, from
+// an imported ARPA model) does not break the line up.
+
+/// `s` with backslashes, spaces and line breaks written as `\\`, `\s`, `\n` and `\r`.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ' ' => out.push_str("\\s"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Undoes `escape`.
+pub fn unescape(s: &str) -> io::Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('s') => ' ',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad escape in {}", s))),
+        });
+    }
+    Ok(out)
+}
+
+/// Writes the double/triple dictionaries and the token list to `path`, sorted so that output is reproducible.
+pub fn save_dictionaries<P>(path: P, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, all_token_list: &[String]) -> io::Result<()>
+where P: AsRef<Path>, {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut doubles: Vec<_> = double_dict.iter().collect();
+    doubles.sort();
+    for (key, count) in doubles {
+        writeln!(out, "double {} {}", count, escape(key))?;
+    }
+    let mut triples: Vec<_> = triple_dict.iter().collect();
+    triples.sort();
+    for (key, count) in triples {
+        writeln!(out, "triple {} {}", count, escape(key))?;
+    }
+    for token in all_token_list {
+        writeln!(out, "token {}", escape(token))?;
+    }
+    out.flush()
+}
+
+/// Reads dictionaries written by `save_dictionaries`.
+pub fn load_dictionaries<P>(path: P) -> io::Result<Dictionaries>
+where P: AsRef<Path>, {
+    let mut double_dict = HashMap::new();
+    let mut triple_dict = HashMap::new();
+    let mut all_token_list = vec![];
+
+    for line in io::BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let mut fields = line.splitn(3, ' ');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("double"), Some(count), Some(key)) => { double_dict.insert(unescape(key)?, parse_count(count)?); }
+            (Some("triple"), Some(count), Some(key)) => { triple_dict.insert(unescape(key)?, parse_count(count)?); }
+            (Some("token"), Some(token), None) => all_token_list.push(unescape(token)?),
+            (Some(""), None, None) => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad dictionary line: {}", line))),
+        }
+    }
+    Ok((double_dict, triple_dict, all_token_list))
+}
+
+fn parse_count(s: &str) -> io::Result<i32> {
+    s.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bad count: {}", s)))
+}
+
+#[test]
+fn test_dictionaries_round_trip() {
+    let mut double_dict = HashMap::new();
+    double_dict.insert("check^pass;".to_string(), 2);
+    double_dict.insert("pass;^user".to_string(), 1);
+    let mut triple_dict = HashMap::new();
+    triple_dict.insert("check^pass;^user".to_string(), 1);
+    triple_dict.insert("a b^c\\d^e\nf".to_string(), 3);
+    let all_token_list = vec!["check".to_string(), "pass;".to_string(), "user".to_string(), "a b".to_string(), "c\\d".to_string(), "\\s\r".to_string()];
+
+    let path = std::env::temp_dir().join(format!("logram-dict-{}.txt", std::process::id()));
+    save_dictionaries(&path, &double_dict, &triple_dict, &all_token_list).unwrap();
+    let loaded = load_dictionaries(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, (double_dict, triple_dict, all_token_list));
+}
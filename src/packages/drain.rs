@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use crate::packages::log_parser::LogParser;
+use crate::packages::parser::{token_splitter, token_splitter_with_spans, FormatConfig};
+use crate::packages::template::Record;
+
+// Drain (He et al., ICWS 2017): lines are routed down a fixed-depth tree by their token count
+// and first few tokens, and at the leaf joined to the most similar cluster, whose template then
+// gets <*> wherever the two differ.
+
+const WILDCARD: &str = "<*>";
+
+#[derive(Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    clusters: Vec<usize>,
+}
+
+pub struct DrainParser {
+    config: FormatConfig,
+    // number of leading tokens used to route a line (tree depth minus the root and length levels)
+    prefix_len: usize,
+    sim_threshold: f64,
+    max_children: usize,
+    root: HashMap<usize, Node>,
+    pub clusters: Vec<Vec<String>>,
+}
+
+impl DrainParser {
+    /// `depth` counts the root and the token-count level, as in the paper; 4 and 0.4 are its defaults.
+    pub fn new(config: &FormatConfig, depth: usize, sim_threshold: f64) -> DrainParser {
+        DrainParser {
+            config: config.clone(),
+            prefix_len: depth.saturating_sub(2),
+            sim_threshold,
+            max_children: 100,
+            root: HashMap::new(),
+            clusters: vec![],
+        }
+    }
+
+    /// Fraction of positions where the template has exactly this token, and the number of
+    /// wildcards in the template (more wildcards wins ties).
+    fn similarity(template: &[String], tokens: &[String]) -> (f64, usize) {
+        let same = template.iter().zip(tokens).filter(|(a, b)| a == b && a.as_str() != WILDCARD).count();
+        let params = template.iter().filter(|t| t.as_str() == WILDCARD).count();
+        (same as f64 / tokens.len() as f64, params)
+    }
+
+    /// The most similar to `tokens` of the `candidates` among `templates`, if any is similar enough.
+    fn best_cluster(templates: &[Vec<String>], candidates: &[usize], tokens: &[String], sim_threshold: f64) -> Option<usize> {
+        candidates.iter()
+            .map(|c| (*c, DrainParser::similarity(&templates[*c], tokens)))
+            .filter(|(_, (sim, _))| *sim >= sim_threshold)
+            .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(c, _)| c)
+    }
+
+    /// Routes `tokens` to their leaf, creating nodes on the way, and returns the matching
+    /// cluster, creating one if nothing is similar enough.
+    fn add(&mut self, tokens: &[String]) -> usize {
+        let max_children = self.max_children;
+        let mut node = self.root.entry(tokens.len()).or_default();
+        for token in tokens.iter().take(self.prefix_len) {
+            let key = if token.chars().any(|c| c.is_ascii_digit()) { WILDCARD } else { token.as_str() };
+            let key = if node.children.contains_key(key) || node.children.len() + 1 < max_children { key } else { WILDCARD };
+            node = node.children.entry(key.to_string()).or_default();
+        }
+
+        match DrainParser::best_cluster(&self.clusters, &node.clusters, tokens, self.sim_threshold) {
+            Some(c) => {
+                for (t, token) in self.clusters[c].iter_mut().zip(tokens) {
+                    if t != token {
+                        *t = WILDCARD.to_string();
+                    }
+                }
+                c
+            }
+            None => {
+                self.clusters.push(tokens.to_vec());
+                node.clusters.push(self.clusters.len() - 1);
+                self.clusters.len() - 1
+            }
+        }
+    }
+
+    /// The cluster `tokens` would join, without changing the tree or the templates.
+    fn matching(&self, tokens: &[String]) -> Option<usize> {
+        let mut node = self.root.get(&tokens.len())?;
+        for token in tokens.iter().take(self.prefix_len) {
+            let key = if token.chars().any(|c| c.is_ascii_digit()) { WILDCARD } else { token.as_str() };
+            node = node.children.get(key).or_else(|| node.children.get(WILDCARD))?;
+        }
+        DrainParser::best_cluster(&self.clusters, &node.clusters, tokens, self.sim_threshold)
+    }
+}
+
+impl LogParser for DrainParser {
+    fn train(&mut self, lines: &[String]) {
+        for line in lines {
+            let tokens = token_splitter(line.to_string(), &self.config);
+            if !tokens.is_empty() {
+                self.add(&tokens);
+            }
+        }
+    }
+
+    // A line is matched against the trained clusters without joining them; a token is dynamic
+    // where the template has <*> or a different token. A line like none seen in training keeps
+    // all its tokens.
+    fn parse(&self, lines: &[String]) -> Vec<Record> {
+        lines.iter().enumerate().map(|(i, raw)| {
+            let (tokens, censored, spans) = token_splitter_with_spans(raw.to_string(), &self.config);
+            let dynamic = match self.matching(&tokens) {
+                None => vec![],
+                Some(c) => self.clusters[c].iter().zip(&tokens).enumerate().filter(|(_, (t, token))| t.as_str() == WILDCARD || t != token).map(|(j, _)| j).collect(),
+            };
+            Record { line_no: i + 1, raw: raw.clone(), tokens, dynamic, censored, spans, timestamp: None }
+        }).collect()
+    }
+}
+
+#[test]
+fn test_drain_parser() {
+    use crate::LogFormat::Spark;
+    use crate::packages::log_parser::template_counts;
+    use crate::packages::parser::read_log_lines;
+
+    let lines = read_log_lines("data/from_paper.log").unwrap();
+    let mut drain = DrainParser::new(&FormatConfig::new(&Spark), 4, 0.4);
+    drain.train(&lines);
+    let records = drain.parse(&lines);
+    assert_eq!(template_counts(&records), vec![
+        ("Input split: <*>".to_string(), 5),
+        ("Found block <*> locally".to_string(), 4),
+    ]);
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::packages::dictionary::{escape, load_dictionaries, save_dictionaries, unescape, Dictionaries};
+use crate::packages::parser::{process_dictionary_builder_line, token_splitter, FormatConfig};
+
+// how much of the start of the file identifies it; a rotated or rewritten log will differ here
+const FINGERPRINT_LEN: u64 = 1024;
+
+/// How far a previous run got through a raw log, plus the token context needed to carry on
+/// exactly where it stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub path: String,
+    pub fingerprint: u64,
+    pub offset: u64,
+    pub prev1: Option<String>,
+    pub prev2: Option<String>,
+    // the token before prev1 in the 3-gram stream; same as prev2 unless the last line had a single token
+    pub lookbehind: Option<String>,
+}
+
+fn fingerprint<R: Read>(reader: R, len: u64) -> io::Result<u64> {
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    // FNV-1a, so that checkpoints stay valid across Rust versions (unlike DefaultHasher)
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    Ok(hash)
+}
+
+fn file_fingerprint(raw_fn: &str, offset: u64) -> io::Result<u64> {
+    fingerprint(File::open(raw_fn)?, offset.min(FINGERPRINT_LEN))
+}
+
+pub fn save_checkpoint<P>(path: P, cp: &Checkpoint) -> io::Result<()>
+where P: AsRef<Path>, {
+    let mut out = File::create(path)?;
+    writeln!(out, "path {}", cp.path)?;
+    writeln!(out, "fingerprint {:016x}", cp.fingerprint)?;
+    writeln!(out, "offset {}", cp.offset)?;
+    for (name, value) in [("prev1", &cp.prev1), ("prev2", &cp.prev2), ("lookbehind", &cp.lookbehind)] {
+        if let Some(v) = value {
+            writeln!(out, "{} {}", name, escape(v))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn load_checkpoint<P>(path: P) -> io::Result<Checkpoint>
+where P: AsRef<Path>, {
+    let bad = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad checkpoint {}", what));
+    let mut cp = Checkpoint { path: String::new(), fingerprint: 0, offset: 0, prev1: None, prev2: None, lookbehind: None };
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let (key, value) = line.split_once(' ').ok_or_else(|| bad("line"))?;
+        match key {
+            "path" => cp.path = value.to_string(),
+            "fingerprint" => cp.fingerprint = u64::from_str_radix(value, 16).map_err(|_| bad("fingerprint"))?,
+            "offset" => cp.offset = value.parse().map_err(|_| bad("offset"))?,
+            "prev1" => cp.prev1 = Some(unescape(value)?),
+            "prev2" => cp.prev2 = Some(unescape(value)?),
+            "lookbehind" => cp.lookbehind = Some(unescape(value)?),
+            _ => return Err(bad("key")),
+        }
+    }
+    Ok(cp)
+}
+
+/// True if `cp` was taken on this file, however its path was written, and the file has only
+/// grown since.
+pub fn checkpoint_matches(raw_fn: &str, cp: &Checkpoint) -> bool {
+    let len = match std::fs::metadata(raw_fn) {
+        Ok(m) => m.len(),
+        Err(_) => return false,
+    };
+    let same_file = match (std::fs::canonicalize(&cp.path), std::fs::canonicalize(raw_fn)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    same_file && len >= cp.offset && file_fingerprint(raw_fn, cp.offset).ok() == Some(cp.fingerprint)
+}
+
+// The last line processed by the previous run had no lookahead, so the 2-gram and 3-grams
+// that span into the first appended line were never counted. A full rebuild counts them
+// while processing that last line, so add them here.
+fn complete_boundary(cp: &Checkpoint, first_line: &str, config: &FormatConfig, dbl: &mut HashMap<String, i32>, trpl: &mut HashMap<String, i32>) {
+    let next_tokens = token_splitter(first_line.to_string(), config);
+    if let (Some(last), Some(next1)) = (&cp.prev1, next_tokens.first()) {
+        *dbl.entry(format!("{}^{}", last, next1)).or_default() += 1;
+        if let Some(before_last) = &cp.lookbehind {
+            *trpl.entry(format!("{}^{}^{}", before_last, last, next1)).or_default() += 1;
+        }
+        if let Some(next2) = next_tokens.get(1) {
+            *trpl.entry(format!("{}^{}^{}", last, next1, next2)).or_default() += 1;
+        }
+    }
+}
+
+/// Adds the lines appended to `raw_fn` since `checkpoint` to the dictionaries, and returns the
+/// new checkpoint. Passing `None` processes the file from the start. Only newline-terminated
+/// lines are consumed; a partially-written last line is left for the next run.
+pub fn update_dictionaries(raw_fn: &str, config: &FormatConfig, checkpoint: Option<Checkpoint>, dbl: &mut HashMap<String, i32>, trpl: &mut HashMap<String, i32>, all_token_list: &mut Vec<String>) -> io::Result<Checkpoint> {
+    let mut cp = checkpoint.unwrap_or(Checkpoint { path: String::new(), fingerprint: 0, offset: 0, prev1: None, prev2: None, lookbehind: None });
+
+    let mut reader = BufReader::new(File::open(raw_fn)?);
+    reader.seek(SeekFrom::Start(cp.offset))?;
+    let mut lines = vec![];
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        let n = reader.read_until(b'\n', &mut buf)?;
+        if n == 0 || buf.last() != Some(&b'\n') {
+            break;
+        }
+        cp.offset += n as u64;
+        // same as read_lines: weirdly-encoded lines are thrown out
+        if let Ok(line) = std::str::from_utf8(&buf) {
+            lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+        }
+    }
+
+    if let Some(first) = lines.first() {
+        complete_boundary(&cp, first, config, dbl, trpl);
+    }
+    let (mut prev1, mut prev2, mut lookbehind) = (cp.prev1.take(), cp.prev2.take(), cp.lookbehind.take());
+    for i in 0..lines.len() {
+        let last_prev1 = prev1.clone();
+        (prev1, prev2) = process_dictionary_builder_line(lines[i].clone(), lines.get(i+1).cloned(), config, dbl, trpl, all_token_list, prev1, prev2);
+        lookbehind = match (&prev1, &prev2) {
+            (Some(_), None) => last_prev1,
+            _ => prev2.clone(),
+        };
+    }
+
+    cp.prev1 = prev1;
+    cp.prev2 = prev2;
+    cp.lookbehind = lookbehind;
+    // absolute, so that the checkpoint still finds the log from another directory
+    cp.path = std::fs::canonicalize(raw_fn)?.to_string_lossy().to_string();
+    cp.fingerprint = file_fingerprint(raw_fn, cp.offset)?;
+    Ok(cp)
+}
+
+/// Loads the dictionaries in `dict_fn` and the checkpoint in `checkpoint_fn` (if both exist and
+/// the checkpoint still matches `raw_fn`), processes only what was appended to `raw_fn`, and
+/// writes both files back. Otherwise rebuilds from scratch.
+pub fn incremental_parse_raw(raw_fn: &str, config: &FormatConfig, dict_fn: &str, checkpoint_fn: &str) -> io::Result<Dictionaries> {
+    let previous = match (load_checkpoint(checkpoint_fn), load_dictionaries(dict_fn)) {
+        (Ok(cp), Ok(dicts)) if checkpoint_matches(raw_fn, &cp) => Some((cp, dicts)),
+        _ => None,
+    };
+    let (checkpoint, (mut double_dict, mut triple_dict, mut all_token_list)) = match previous {
+        Some((cp, dicts)) => {
+            println!("resuming {} from byte {}", raw_fn, cp.offset);
+            (Some(cp), dicts)
+        }
+        None => {
+            println!("no usable checkpoint for {}, building from scratch", raw_fn);
+            (None, (HashMap::new(), HashMap::new(), vec![]))
+        }
+    };
+
+    let cp = update_dictionaries(raw_fn, config, checkpoint, &mut double_dict, &mut triple_dict, &mut all_token_list)?;
+    save_dictionaries(dict_fn, &double_dict, &triple_dict, &all_token_list)?;
+    save_checkpoint(checkpoint_fn, &cp)?;
+    println!("double dictionary list len {}, triple {}, all tokens {}", double_dict.len(), triple_dict.len(), all_token_list.len());
+    Ok((double_dict, triple_dict, all_token_list))
+}
+
+#[test]
+fn test_incremental_matches_full_rebuild() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::dictionary_builder;
+
+    let all_lines: Vec<String> = std::fs::read_to_string("data/Linux_2k.log").unwrap().lines().map(|l| l.to_string()).collect();
+    let dir = std::env::temp_dir();
+    let full_fn = dir.join(format!("logram-full-{}.log", std::process::id())).to_str().unwrap().to_string();
+    let grow_fn = dir.join(format!("logram-grow-{}.log", std::process::id())).to_str().unwrap().to_string();
+    // the grown log by another path
+    let same_fn = dir.join(".").join(format!("logram-grow-{}.log", std::process::id())).to_str().unwrap().to_string();
+    std::fs::write(&full_fn, all_lines.join("\n") + "\n").unwrap();
+
+    let (mut dbl, mut trpl, mut all_token_list) = (HashMap::new(), HashMap::new(), vec![]);
+    let mut cp = None;
+    // line 714 is "restart." alone, which exercises lookbehind
+    for end in [714, 1200, 1201, all_lines.len()] {
+        std::fs::write(&grow_fn, all_lines[..end].join("\n") + "\n").unwrap();
+        let c = update_dictionaries(&grow_fn, &FormatConfig::new(&Linux), cp, &mut dbl, &mut trpl, &mut all_token_list).unwrap();
+        assert!(checkpoint_matches(&grow_fn, &c));
+        assert!(checkpoint_matches(&same_fn, &c) && !checkpoint_matches(&full_fn, &c));
+        cp = Some(c);
+    }
+    let (full_dbl, full_trpl, full_all_token_list) = dictionary_builder(full_fn.clone(), FormatConfig::new(&Linux));
+    std::fs::remove_file(&full_fn).unwrap();
+    std::fs::remove_file(&grow_fn).unwrap();
+
+    assert_eq!(dbl, full_dbl);
+    assert_eq!(trpl, full_trpl);
+    assert_eq!(all_token_list, full_all_token_list);
+}
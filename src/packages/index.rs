@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::packages::log_parser::LogParser;
+use crate::packages::structured::raw_lines;
+use crate::packages::template::Record;
+
+// An inverted index from templates to the lines they cover. Each line keeps its byte range in
+// the log and its parameter values, so that lines can be found by template and parameter from
+// the index alone, and only the lines found are read back from the log.
+//
+// On-disk format, one entry per line:
+//   log <length> <mtime in ns> <path of the indexed log, relative to the index>
+//   template <id> <line count> <template>
+//   slot <label> <context>                  one per parameter of the template above, in order
+//   line <line no> <offset> <length>[\t<parameter>]...
+// Every slot and line belongs to the last template before it. Parameters are escaped so that
+// they hold no tab or newline. The log's length and modification time tell whether it changed
+// since it was indexed, in which case the offsets can no longer be trusted.
+
+/// Where a parameter sits in its template: its label (<*> or a placeholder such as <IP>), and
+/// the template text around it, e.g. rhost=<IP>.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub label: String,
+    pub context: String,
+}
+
+/// A line of the log: its number (from 1), its byte range, and its parameter values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub line_no: usize,
+    pub offset: u64,
+    pub len: usize,
+    pub params: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateEntry {
+    pub template: String,
+    pub slots: Vec<Slot>,
+    pub lines: Vec<Posting>,
+}
+
+/// Templates in order of first appearance; a template's id is its position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub log: String,
+    pub log_len: u64,
+    pub log_mtime: u128,
+    pub templates: Vec<TemplateEntry>,
+}
+
+// length and modification time of a file
+fn stamp(metadata: &std::fs::Metadata) -> io::Result<(u64, u128)> {
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    Ok((metadata.len(), mtime))
+}
+
+fn slots(record: &Record) -> Vec<Slot> {
+    let template: Vec<&str> = record.tokens.iter().enumerate()
+        .map(|(i, t)| if record.dynamic.contains(&i) { "<*>" } else { t.as_str() })
+        .collect();
+    record.labelled_parameters().into_iter().map(|p| {
+        let token = template[p.token];
+        // a key= the tokenizer split off belongs to its value
+        let context = match p.token.checked_sub(1).map(|i| template[i]) {
+            Some(key) if token == p.label && key.ends_with('=') => format!("{}{}", key, token),
+            _ => token.to_string(),
+        };
+        Slot { label: p.label, context }
+    }).collect()
+}
+
+/// Parses the lines of `bytes`, the contents of the log at `log`, and indexes them by template.
+/// Lines without content tokens are left out.
+pub fn build_index(log: &str, bytes: &[u8], parser: &dyn LogParser) -> io::Result<Index> {
+    let (log_len, log_mtime) = stamp(&std::fs::metadata(log)?)?;
+    let lines = raw_lines(bytes);
+    let texts: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+    let records = parser.parse(&texts);
+
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut templates: Vec<TemplateEntry> = vec![];
+    let mut offset = 0;
+    for (i, (record, line)) in records.iter().zip(&lines).enumerate() {
+        let len = line.to_bytes().len();
+        if !record.tokens.is_empty() {
+            let template = record.template();
+            let id = *ids.entry(template.clone()).or_insert_with(|| {
+                templates.push(TemplateEntry { template, slots: slots(record), lines: vec![] });
+                templates.len() - 1
+            });
+            templates[id].lines.push(Posting { line_no: i + 1, offset, len, params: record.parameters() });
+        }
+        offset += len as u64 + 1;
+    }
+    Ok(Index { log: log.to_string(), log_len, log_mtime, templates })
+}
+
+impl TemplateEntry {
+    /// The slot called `name`: a position (from 1), a label with or without its brackets, or
+    /// a context. The first matching slot wins.
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        if let Ok(n) = name.parse::<usize>() {
+            return n.checked_sub(1).filter(|i| *i < self.slots.len());
+        }
+        self.slots.iter().position(|s| s.label == name || s.context == name || s.label.trim_matches(|c| c == '<' || c == '>') == name)
+    }
+
+    /// Splits a constraint such as rhost=<IP>=218.22.3.51 into a slot and the value it must
+    /// have. Slot names may contain = themselves, so the first split that names a slot is taken.
+    pub fn constraint<'a>(&self, constraint: &'a str) -> Option<(usize, &'a str)> {
+        constraint.match_indices('=')
+            .find_map(|(at, _)| self.slot(&constraint[..at]).map(|slot| (slot, &constraint[at + 1..])))
+    }
+
+    /// The lines of this template, or only those whose parameter in `slot` is `value`.
+    pub fn search(&self, constraint: Option<(usize, &str)>) -> Vec<&Posting> {
+        self.lines.iter()
+            .filter(|p| constraint.is_none_or(|(slot, value)| p.params.get(slot).map(|v| v.as_str()) == Some(value)))
+            .collect()
+    }
+}
+
+impl Index {
+    /// The id of `template`, given either as an id or as the template text.
+    pub fn find_template(&self, template: &str) -> Option<usize> {
+        match template.parse::<usize>() {
+            Ok(id) if id < self.templates.len() => Some(id),
+            _ => self.templates.iter().position(|t| t.template == template),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some(other) => other,
+            None => '\\',
+        });
+    }
+    out
+}
+
+// the directory an index file is in, as an absolute path
+fn index_dir(index: &Path) -> io::Result<PathBuf> {
+    match index.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.canonicalize(),
+        _ => Path::new(".").canonicalize(),
+    }
+}
+
+// `path` relative to the directory `base`, both absolute
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let common = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    let mut relative: PathBuf = base.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(path.components().skip(common));
+    relative
+}
+
+/// Writes `index` to `path`. The log's path is stored relative to the index, so that the two
+/// can move together and the index works from any directory.
+pub fn save_index<P>(path: P, index: &Index) -> io::Result<()>
+where P: AsRef<Path>, {
+    let log = relative_to(&Path::new(&index.log).canonicalize()?, &index_dir(path.as_ref())?);
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "log {} {} {}", index.log_len, index.log_mtime, log.display())?;
+    for (id, entry) in index.templates.iter().enumerate() {
+        writeln!(out, "template {} {} {}", id, entry.lines.len(), entry.template)?;
+        for slot in &entry.slots {
+            writeln!(out, "slot {} {}", slot.label, slot.context)?;
+        }
+        for p in &entry.lines {
+            write!(out, "line {} {} {}", p.line_no, p.offset, p.len)?;
+            for param in &p.params {
+                write!(out, "\t{}", escape(param))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+fn bad_index(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad index line: {}", line))
+}
+
+/// Reads an index written by `save_index`.
+pub fn load_index<P>(path: P) -> io::Result<Index>
+where P: AsRef<Path>, {
+    let dir = index_dir(path.as_ref())?;
+    let mut index = Index { log: String::new(), log_len: 0, log_mtime: 0, templates: vec![] };
+    for line in io::BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let (kind, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match kind {
+            "log" => {
+                let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+                match fields[..] {
+                    [len, mtime, log] => {
+                        index.log_len = len.parse().map_err(|_| bad_index(&line))?;
+                        index.log_mtime = mtime.parse().map_err(|_| bad_index(&line))?;
+                        index.log = dir.join(log).to_string_lossy().to_string();
+                    }
+                    _ => return Err(bad_index(&line)),
+                }
+            }
+            "template" => {
+                let fields: Vec<&str> = rest.splitn(3, ' ').collect();
+                match fields.first().and_then(|id| id.parse::<usize>().ok()) {
+                    Some(id) if id == index.templates.len() && fields.len() == 3 => {}
+                    _ => return Err(bad_index(&line)),
+                }
+                index.templates.push(TemplateEntry { template: fields[2].to_string(), slots: vec![], lines: vec![] });
+            }
+            "slot" => {
+                let (label, context) = rest.split_once(' ').ok_or_else(|| bad_index(&line))?;
+                let entry = index.templates.last_mut().ok_or_else(|| bad_index(&line))?;
+                entry.slots.push(Slot { label: label.to_string(), context: context.to_string() });
+            }
+            "line" => {
+                let mut fields = rest.split('\t');
+                let position: Vec<&str> = fields.next().unwrap().split(' ').collect();
+                let (line_no, offset, len) = match position[..] {
+                    [n, o, l] => (n.parse().ok(), o.parse().ok(), l.parse().ok()),
+                    _ => (None, None, None),
+                };
+                let params = fields.map(unescape).collect();
+                let entry = index.templates.last_mut().ok_or_else(|| bad_index(&line))?;
+                match (line_no, offset, len) {
+                    (Some(line_no), Some(offset), Some(len)) => entry.lines.push(Posting { line_no, offset, len, params }),
+                    _ => return Err(bad_index(&line)),
+                }
+            }
+            "" => {}
+            _ => return Err(bad_index(&line)),
+        }
+    }
+    Ok(index)
+}
+
+/// Reads the lines at `postings` from the indexed log, seeking to each instead of reading it
+/// all. Fails if the log changed since it was indexed.
+pub fn read_postings(index: &Index, postings: &[&Posting]) -> io::Result<Vec<Vec<u8>>> {
+    let mut file = File::open(&index.log)?;
+    if stamp(&file.metadata()?)? != (index.log_len, index.log_mtime) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed since it was indexed", index.log)));
+    }
+    postings.iter().map(|p| {
+        file.seek(SeekFrom::Start(p.offset))?;
+        let mut line = vec![0; p.len];
+        file.read_exact(&mut line)?;
+        Ok(line)
+    }).collect()
+}
+
+/// Prints each template with its id, how many lines it covers, and its slots.
+pub fn print_index(index: &Index) {
+    for (id, entry) in index.templates.iter().enumerate() {
+        println!("{}\t{}\t{}", id, entry.lines.len(), entry.template);
+        for (i, slot) in entry.slots.iter().enumerate() {
+            println!("\t\t{} {} {}", i + 1, slot.label, slot.context);
+        }
+    }
+}
+
+#[test]
+fn test_index_and_search() {
+    use crate::LogFormat::Linux;
+    use crate::packages::log_parser::NgramParser;
+    use crate::packages::parser::FormatConfig;
+
+    let log = "data/Linux_2k.log";
+    let bytes = std::fs::read(log).unwrap();
+    let texts: Vec<String> = raw_lines(&bytes).into_iter().map(|l| l.text).collect();
+    let mut parser = NgramParser::new(&FormatConfig::split_punctuation(&Linux), 3);
+    parser.train(&texts);
+    let index = build_index(log, &bytes, &parser).unwrap();
+
+    let path = std::env::temp_dir().join(format!("logram-index-{}.txt", std::process::id()));
+    save_index(&path, &index).unwrap();
+    let loaded = load_index(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // the log is found from wherever the index is
+    assert_eq!(Path::new(&loaded.log).canonicalize().unwrap(), Path::new(log).canonicalize().unwrap());
+    assert_eq!(Index { log: index.log.clone(), ..loaded }, index);
+    assert_eq!(index.templates.iter().map(|t| t.lines.len()).sum::<usize>(), texts.iter().filter(|t| !t.trim().is_empty()).count());
+
+    // every posting points at its own line
+    let entry = &index.templates[index.find_template("0").unwrap()];
+    let all = entry.search(None);
+    for (p, line) in all.iter().zip(read_postings(&index, &all).unwrap()) {
+        assert_eq!(line, texts[p.line_no - 1].as_bytes());
+    }
+
+    let id = index.templates.iter().position(|t| t.template.ends_with("rhost= <IP> user= root")).unwrap();
+    let entry = &index.templates[id];
+    assert_eq!(index.find_template(&entry.template), Some(id));
+    let (slot, value) = entry.constraint("rhost=<IP>=218.22.3.51").unwrap();
+    assert_eq!(value, "218.22.3.51");
+    assert_eq!(entry.constraint("IP=218.22.3.51"), Some((slot, value)));
+    let found = entry.search(Some((slot, value)));
+    assert!(!found.is_empty() && found.len() < entry.lines.len());
+    for line in read_postings(&index, &found).unwrap() {
+        assert!(String::from_utf8(line).unwrap().contains("rhost=218.22.3.51"));
+    }
+    assert!(entry.constraint("nope=1").is_none());
+}
+
+#[test]
+fn test_stale_index() {
+    use crate::LogFormat::Linux;
+    use crate::packages::log_parser::NgramParser;
+    use crate::packages::parser::FormatConfig;
+
+    let dir = std::env::temp_dir().join(format!("logram-stale-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("Linux.log");
+    let bytes: Vec<u8> = std::fs::read("data/Linux_2k.log").unwrap().split(|b| *b == b'\n').take(50).collect::<Vec<_>>().join(&b'\n');
+    std::fs::write(&log, &bytes).unwrap();
+    let texts: Vec<String> = raw_lines(&bytes).into_iter().map(|l| l.text).collect();
+    let mut parser = NgramParser::new(&FormatConfig::new(&Linux), 3);
+    parser.train(&texts);
+    save_index(dir.join("linux.idx"), &build_index(log.to_str().unwrap(), &bytes, &parser).unwrap()).unwrap();
+    assert!(std::fs::read_to_string(dir.join("linux.idx")).unwrap().lines().next().unwrap().ends_with(" Linux.log"));
+
+    let index = load_index(dir.join("linux.idx")).unwrap();
+    let all = index.templates[0].search(None);
+    assert!(read_postings(&index, &all).is_ok());
+    std::fs::write(&log, [&bytes[..], b"\nmore"].concat()).unwrap();
+    assert_eq!(read_postings(&index, &all).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
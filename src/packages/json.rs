@@ -0,0 +1,24 @@
+// Just enough JSON to print results without pulling in a serializer.
+
+/// `s` as a quoted JSON string.
+pub fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("a \"b\"\\\n\u{1}"), r#""a \"b\"\\\n\u0001""#);
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::packages::parser::{process_dictionary_builder_line, FormatConfig};
+use crate::packages::template::{extract_records, Record};
+
+/// A way of turning log lines into templates. `train` learns from a log; `parse` assigns a
+/// template to every line of a (possibly different) log, marking the dynamic tokens of each
+/// record, without learning from it. All backends tokenize with `token_splitter`, so their
+/// records are comparable.
+pub trait LogParser {
+    fn train(&mut self, lines: &[String]);
+    fn parse(&self, lines: &[String]) -> Vec<Record>;
+}
+
+/// The logram n-gram dictionary approach.
+pub struct NgramParser {
+    config: FormatConfig,
+    cutoff: i32,
+    pub double_dict: HashMap<String, i32>,
+    pub triple_dict: HashMap<String, i32>,
+    pub all_token_list: Vec<String>,
+}
+
+impl NgramParser {
+    pub fn new(config: &FormatConfig, cutoff: i32) -> NgramParser {
+        NgramParser {
+            config: config.clone(),
+            cutoff,
+            double_dict: HashMap::new(),
+            triple_dict: HashMap::new(),
+            all_token_list: vec![],
+        }
+    }
+
+    /// Uses dictionaries that were already built (or loaded) instead of training.
+    pub fn with_dictionaries(config: &FormatConfig, cutoff: i32, double_dict: HashMap<String, i32>, triple_dict: HashMap<String, i32>) -> NgramParser {
+        NgramParser { double_dict, triple_dict, ..NgramParser::new(config, cutoff) }
+    }
+}
+
+impl LogParser for NgramParser {
+    fn train(&mut self, lines: &[String]) {
+        let (mut prev1, mut prev2) = (None, None);
+        for i in 0..lines.len() {
+            (prev1, prev2) = process_dictionary_builder_line(lines[i].clone(), lines.get(i+1).cloned(), &self.config, &mut self.double_dict, &mut self.triple_dict, &mut self.all_token_list, prev1, prev2);
+        }
+    }
+
+    fn parse(&self, lines: &[String]) -> Vec<Record> {
+        extract_records(lines, &self.config, &self.double_dict, &self.triple_dict, self.cutoff)
+    }
+}
+
+/// Distinct templates of `records`, most frequent first, with their counts.
+pub fn template_counts(records: &[Record]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for r in records.iter().filter(|r| !r.tokens.is_empty()) {
+        *counts.entry(r.template()).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+pub fn print_templates(records: &[Record]) {
+    let counts = template_counts(records);
+    println!("{} templates", counts.len());
+    for (template, count) in counts {
+        println!("{}: {}", count, template);
+    }
+}
+
+#[test]
+fn test_ngram_parser_matches_parse_raw() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::{dictionary_builder, read_log_lines};
+
+    let lines = read_log_lines("data/Linux_2k.log").unwrap();
+    let mut parser = NgramParser::new(&FormatConfig::new(&Linux), 100);
+    parser.train(&lines);
+    let (double_dict, triple_dict, _) = dictionary_builder("data/Linux_2k.log".to_string(), FormatConfig::new(&Linux));
+    assert_eq!(parser.double_dict, double_dict);
+    assert_eq!(parser.triple_dict, triple_dict);
+    assert_eq!(parser.parse(&lines).len(), lines.len());
+}
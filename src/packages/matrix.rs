@@ -0,0 +1,110 @@
+use std::io::{self, Write};
+
+use crate::packages::csv::row;
+use crate::packages::sessions::Sessions;
+use crate::packages::timeseries::TimeSeries;
+use crate::packages::timestamp::format_timestamp;
+
+// Event count matrices: a row per session or time window, a column per template id, and how
+// often the template occurs in the row, as the usual machine-learning detectors take them.
+//
+// The binary format is for loading without a CSV parser, e.g. with numpy.fromfile:
+//   the magic bytes LOGRAMM1
+//   the row count and the column count, as little-endian u64
+//   the values, row by row, as little-endian f64
+// Row labels are only in the CSV; rows come in the same order in both.
+
+const MAGIC: &[u8] = b"LOGRAMM1";
+
+pub struct CountMatrix {
+    /// session identifiers or window starts
+    pub rows: Vec<String>,
+    pub columns: usize,
+    pub values: Vec<Vec<f64>>,
+}
+
+/// A row per session, in the order of `sessions`.
+pub fn session_matrix(sessions: &Sessions) -> CountMatrix {
+    let columns = sessions.templates.len();
+    let values = sessions.sessions.iter().map(|(_, sequence)| {
+        let mut counts = vec![0.0; columns];
+        for t in sequence {
+            counts[*t] += 1.0;
+        }
+        counts
+    }).collect();
+    CountMatrix { rows: sessions.sessions.iter().map(|(id, _)| id.clone()).collect(), columns, values }
+}
+
+/// A row per bucket of `series`, empty buckets included.
+pub fn window_matrix(series: &TimeSeries) -> CountMatrix {
+    let columns = series.templates.len();
+    let buckets = series.buckets();
+    let values = buckets.iter().map(|b| (0..columns).map(|t| series.count(*b, t) as f64).collect()).collect();
+    CountMatrix { rows: buckets.into_iter().map(format_timestamp).collect(), columns, values }
+}
+
+impl CountMatrix {
+    /// Weighs every count by the inverse document frequency of its template, ln(rows / rows
+    /// the template occurs in), so that templates in every row count for nothing.
+    pub fn tf_idf(&mut self) {
+        let n = self.values.len() as f64;
+        let idf: Vec<f64> = (0..self.columns).map(|t| {
+            let df = self.values.iter().filter(|r| r[t] > 0.0).count();
+            if df == 0 { 0.0 } else { (n / df as f64).ln() }
+        }).collect();
+        for r in self.values.iter_mut() {
+            for (v, w) in r.iter_mut().zip(&idf) {
+                *v *= w;
+            }
+        }
+    }
+}
+
+/// A header of template ids, then each row's label and values.
+pub fn write_csv<W: Write>(out: &mut W, matrix: &CountMatrix) -> io::Result<()> {
+    let header: Vec<String> = std::iter::once("row".to_string()).chain((0..matrix.columns).map(|t| t.to_string())).collect();
+    writeln!(out, "{}", row(&header))?;
+    for (label, values) in matrix.rows.iter().zip(&matrix.values) {
+        let fields: Vec<String> = std::iter::once(label.clone()).chain(values.iter().map(|v| v.to_string())).collect();
+        writeln!(out, "{}", row(&fields))?;
+    }
+    Ok(())
+}
+
+pub fn write_binary<W: Write>(out: &mut W, matrix: &CountMatrix) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&(matrix.values.len() as u64).to_le_bytes())?;
+    out.write_all(&(matrix.columns as u64).to_le_bytes())?;
+    for values in &matrix.values {
+        for v in values {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_count_matrix() {
+    let sessions = Sessions {
+        templates: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        sessions: vec![("s1".to_string(), vec![0, 1, 1]), ("s2".to_string(), vec![0, 2])],
+    };
+    let mut matrix = session_matrix(&sessions);
+    assert_eq!(matrix.values, vec![vec![1.0, 2.0, 0.0], vec![1.0, 0.0, 1.0]]);
+
+    let mut csv = vec![];
+    write_csv(&mut csv, &matrix).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap(), "row,0,1,2\ns1,1,2,0\ns2,1,0,1\n");
+
+    let mut binary = vec![];
+    write_binary(&mut binary, &matrix).unwrap();
+    assert_eq!(binary.len(), 8 + 16 + 6 * 8);
+    assert_eq!(&binary[8..16], &2u64.to_le_bytes());
+    assert_eq!(&binary[24 + 8..32 + 8], &2.0f64.to_le_bytes());
+
+    // a occurs in every session, so it weighs nothing
+    matrix.tf_idf();
+    let ln2 = 2.0f64.ln();
+    assert_eq!(matrix.values, vec![vec![0.0, 2.0 * ln2, 0.0], vec![0.0, 0.0, ln2]]);
+}
@@ -1 +1,29 @@
 pub mod parser;
+pub mod patterns;
+pub mod classify;
+pub mod tokenizer;
+pub mod json;
+pub mod csv;
+pub mod dictionary;
+pub mod incremental;
+pub mod window;
+pub mod template;
+pub mod timestamp;
+pub mod timeseries;
+pub mod bursts;
+pub mod sessions;
+pub mod matrix;
+pub mod transitions;
+pub mod cooccurrence;
+pub mod paramstats;
+pub mod refine;
+pub mod structured;
+pub mod compress;
+pub mod index;
+pub mod detect;
+pub mod score;
+pub mod arpa;
+pub mod log_parser;
+pub mod drain;
+pub mod spell;
+pub mod benchmark;
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::packages::template::Record;
+
+// What the <*> positions of each template hold. A position with thousands of distinct values
+// is an identifier; one with two or three is more likely a small enum that the n-gram cutoff
+// marked dynamic and that would be better left static.
+
+/// How many example values to keep for a position.
+const EXAMPLES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionStats {
+    /// lines the template covers, which is also how many values the position has
+    pub count: usize,
+    pub distinct: usize,
+    /// the most frequent values with their counts, most frequent first
+    pub top: Vec<(String, usize)>,
+    /// statistics of the values that are numbers, if any are
+    pub numeric: Option<NumericStats>,
+    /// the first few distinct values, in order of appearance
+    pub examples: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumericStats {
+    /// how many of the values are numbers; the rest are left out of min, max and mean
+    pub parsed: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateStats {
+    pub template: String,
+    pub count: usize,
+    /// one entry per <*> in the template, in order
+    pub positions: Vec<PositionStats>,
+}
+
+fn position_stats(values: &[String], top_k: usize) -> PositionStats {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut examples = vec![];
+    for v in values {
+        let c = counts.entry(v.as_str()).or_insert(0);
+        if *c == 0 && examples.len() < EXAMPLES {
+            examples.push(v.clone());
+        }
+        *c += 1;
+    }
+    let mut top: Vec<(String, usize)> = counts.iter().map(|(v, c)| (v.to_string(), *c)).collect();
+    top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    top.truncate(top_k);
+
+    let numbers: Vec<f64> = values.iter().filter_map(|v| v.parse::<f64>().ok().filter(|n| n.is_finite())).collect();
+    let numeric = match numbers.is_empty() {
+        true => None,
+        false => Some(NumericStats {
+            parsed: numbers.len(),
+            min: numbers.iter().copied().fold(f64::INFINITY, f64::min),
+            max: numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: numbers.iter().sum::<f64>() / numbers.len() as f64,
+        }),
+    };
+    PositionStats { count: values.len(), distinct: counts.len(), top, numeric, examples }
+}
+
+/// Statistics for every <*> position of every template of `records`, templates most frequent
+/// first. Templates without a <*> are left out.
+pub fn parameter_stats(records: &[Record], top_k: usize) -> Vec<TemplateStats> {
+    // each template's values, position by position
+    let mut values: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for record in records.iter().filter(|r| !r.dynamic.is_empty()) {
+        let template = record.template();
+        *counts.entry(template.clone()).or_insert(0) += 1;
+        let positions = values.entry(template).or_insert_with(|| vec![vec![]; record.dynamic.len()]);
+        let dynamic = record.labelled_parameters().into_iter().filter(|p| p.label == "<*>");
+        for (position, p) in positions.iter_mut().zip(dynamic) {
+            position.push(p.value);
+        }
+    }
+
+    let mut stats: Vec<TemplateStats> = values.into_iter().map(|(template, positions)| TemplateStats {
+        count: counts[&template],
+        positions: positions.iter().map(|v| position_stats(v, top_k)).collect(),
+        template,
+    }).collect();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.template.cmp(&b.template)));
+    stats
+}
+
+pub fn print_parameter_stats(stats: &[TemplateStats]) {
+    for t in stats {
+        println!("{}: {}", t.count, t.template);
+        for (i, p) in t.positions.iter().enumerate() {
+            let top: Vec<String> = p.top.iter().map(|(v, c)| format!("{} ({})", v, c)).collect();
+            print!("    <*> {}: {} distinct; top {}", i + 1, p.distinct, top.join(", "));
+            if let Some(n) = &p.numeric {
+                print!("; {} of {} numeric, min {} max {} mean {:.2}", n.parsed, p.count, n.min, n.max, n.mean);
+            }
+            println!("; e.g. {}", p.examples.join(", "));
+        }
+    }
+}
+
+#[test]
+fn test_parameter_stats() {
+    let record = |tokens: &[&str], dynamic: Vec<usize>| Record {
+        line_no: 0, raw: String::new(), tokens: tokens.iter().map(|t| t.to_string()).collect(),
+        dynamic, censored: vec![], spans: vec![], timestamp: None,
+    };
+    let records = vec![
+        record(&["user", "alice", "took", "12"], vec![1, 3]),
+        record(&["user", "bob", "took", "3.5"], vec![1, 3]),
+        record(&["user", "alice", "took", "-1"], vec![1, 3]),
+        record(&["user", "carol", "took", "n/a"], vec![1, 3]),
+        record(&["started"], vec![]),
+        record(&["disk", "sda", "full"], vec![1]),
+    ];
+    let stats = parameter_stats(&records, 1);
+    assert_eq!(stats.len(), 2);
+    assert_eq!((stats[0].template.as_str(), stats[0].count), ("user <*> took <*>", 4));
+
+    let users = &stats[0].positions[0];
+    assert_eq!((users.count, users.distinct), (4, 3));
+    assert_eq!(users.top, vec![("alice".to_string(), 2)]);
+    assert_eq!(users.numeric, None);
+    assert_eq!(users.examples, vec!["alice", "bob", "carol"]);
+
+    // n/a is left out
+    let times = &stats[0].positions[1];
+    assert_eq!(times.numeric, Some(NumericStats { parsed: 3, min: -1.0, max: 12.0, mean: 14.5 / 3.0 }));
+    assert_eq!(stats[1].positions[0].examples, vec!["sda"]);
+}
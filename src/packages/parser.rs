@@ -18,6 +18,9 @@ use crate::LogFormat::HPC;
 use crate::LogFormat::Proxifier;
 use crate::LogFormat::Android;
 use crate::LogFormat::HealthApp;
+use crate::packages::patterns::{pattern, patterns};
+use crate::packages::classify::{classify, TokenClass};
+use crate::packages::tokenizer::Tokenizer;
 
 pub fn format_string(lf: &LogFormat) -> String {
     match lf {
@@ -40,44 +43,88 @@ pub fn format_string(lf: &LogFormat) -> String {
     }
 }
 
+/// Censoring regexps for each format. Wrapping one in a named group, e.g. (?P<IP>...), makes
+/// it leave <IP> instead of <*> in tokens, templates and n-grams.
 pub fn censored_regexps(lf: &LogFormat) -> Vec<Regex> {
     match lf {
         Linux =>
-            vec![Regex::new(r"(\d+\.){3}\d+").unwrap(),
-                 Regex::new(r"\w{3} \w{3} \d{2} \d{2}:\d{2}:\d{2} \d{4}").unwrap(),
-                 Regex::new(r"\d{2}:\d{2}:\d{2}").unwrap()],
+            vec![pattern("IP").unwrap(),
+                 Regex::new(r"(?P<DATETIME>\w{3} \w{3} \d{2} \d{2}:\d{2}:\d{2} \d{4})").unwrap(),
+                 Regex::new(r"(?P<TIME>\d{2}:\d{2}:\d{2})").unwrap()],
         OpenStack =>
-            vec![Regex::new(r"((\d+\.){3}\d+,?)+").unwrap(),
-                 Regex::new(r"/.+?\s").unwrap()],
+            vec![pattern("IP_LIST").unwrap(),
+                 Regex::new(r"(?P<PATH>/.+?\s)").unwrap()],
         // I commented out Regex::new(r"\d+").unwrap() because that censors all numbers, which may not be what we want?
         Spark =>
-            vec![Regex::new(r"(\d+\.){3}\d+").unwrap(),
-                 Regex::new(r"\b[KGTM]?B\b").unwrap(), 
-                 Regex::new(r"([\w-]+\.){2,}[\w-]+").unwrap()],
+            vec![pattern("IP").unwrap(),
+                 Regex::new(r"(?P<SIZE>\b[KGTM]?B\b)").unwrap(), 
+                 pattern("HOST").unwrap()],
         HDFS =>
-            vec![Regex::new(r"blk_(|-)[0-9]+").unwrap(), // block id
-                Regex::new(r"(/|)([0-9]+\.){3}[0-9]+(:[0-9]+|)(:|)").unwrap() // IP
+            vec![Regex::new(r"(?P<BLK>blk_(|-)[0-9]+)").unwrap(), // block id
+                pattern("IP_PORT").unwrap(),
+                // numbers are censored token by token, see token_classes, since rust regexps have no lookbehind
                 ],
-        // oops, numbers require lookbehind, which rust doesn't support, sigh
-        //                Regex::new(r"(?<=[^A-Za-z0-9])(\-?\+?\d+)(?=[^A-Za-z0-9])|[0-9]+$").unwrap()]; // Numbers
         HPC =>
-            vec![Regex::new(r"=\d+").unwrap()],
+            vec![Regex::new(r"=(?P<NUM>\d+)").unwrap()],
         Proxifier =>
-            vec![Regex::new(r"<\d+\ssec").unwrap(),
-                 Regex::new(r"([\w-]+\.)+[\w-]+(:\d+)?").unwrap(),
-                 Regex::new(r"\d{2}:\d{2}(:\d{2})*").unwrap(),
-                 Regex::new(r"[KGTM]B").unwrap()],
+            vec![Regex::new(r"(?P<DURATION><\d+\ssec)").unwrap(),
+                 pattern("HOST_PORT").unwrap(),
+                 Regex::new(r"(?P<TIME>\d{2}:\d{2}(:\d{2})*)").unwrap(),
+                 Regex::new(r"(?P<SIZE>[KGTM]B)").unwrap()],
         Android =>
-            vec![Regex::new(r"(/[\w-]+)+").unwrap(),
-                 Regex::new(r"([\w-]+\.){2,}[\w-]+").unwrap(),
-                 Regex::new(r"\b(\-?\+?\d+)\b|\b0[Xx][a-fA-F\d]+\b|\b[a-fA-F\d]{4,}\b").unwrap()],
-        HealthApp => vec![],
+            vec![Regex::new(r"(?P<PATH>(/[\w-]+)+)").unwrap(),
+                 pattern("HOST").unwrap(),
+                 Regex::new(r"(?P<NUM>\b(\-?\+?\d+)\b|\b0[Xx][a-fA-F\d]+\b|\b[a-fA-F\d]{4,}\b)").unwrap()],
+        // step-counter callbacks carry millisecond timestamps, e.g. onExtend:1514038530000
+        HealthApp => patterns(&["EPOCH"]),
+    }
+}
+
+/// How --split-punctuation cuts content into tokens after censoring. Linux and HPC messages glue
+/// punctuation, brackets and key=value pairs to their words; the other formats split on whitespace.
+pub fn punctuation_tokenizer(lf: &LogFormat) -> Tokenizer {
+    match lf {
+        Linux => Tokenizer { delimiters: ";,".to_string(), key_value: true, strip_brackets: true },
+        HPC => Tokenizer { delimiters: ",".to_string(), key_value: true, strip_brackets: true },
+        _ => Tokenizer::whitespace(),
+    }
+}
+
+/// Token classes censored after splitting: a token whose class (see classify.rs) is listed is
+/// replaced by the class placeholder, e.g. <INT>. HDFS censors bare numbers this way, leaving
+/// the digits in ids like blk_123 or dfs.DataNode$PacketResponder alone.
+pub fn token_classes(lf: &LogFormat) -> Vec<TokenClass> {
+    match lf {
+        HDFS => vec![TokenClass::Integer],
+        _ => vec![],
+    }
+}
+
+/// What it takes to split a line of one format into tokens: the regexp for its header, the
+/// censoring regexps, the tokenizer and the token classes to censor. `new` splits on whitespace.
+#[derive(Debug, Clone)]
+pub struct FormatConfig {
+    pub re: Regex,
+    pub censors: Vec<Regex>,
+    pub tokenizer: Tokenizer,
+    pub classes: Vec<TokenClass>,
+}
+
+impl FormatConfig {
+    pub fn new(lf: &LogFormat) -> FormatConfig {
+        FormatConfig { re: regex_generator(format_string(lf)), censors: censored_regexps(lf), tokenizer: Tokenizer::whitespace(), classes: token_classes(lf) }
+    }
+
+    /// Like `new`, with the format's `punctuation_tokenizer`.
+    pub fn split_punctuation(lf: &LogFormat) -> FormatConfig {
+        FormatConfig { tokenizer: punctuation_tokenizer(lf), ..FormatConfig::new(lf) }
     }
 }
 
 // https://doc.rust-lang.org/rust-by-example/std_misc/file/read_lines.html
 // The output is wrapped in a Result to allow matching on errors
 // Returns an Iterator to the Reader of the lines of the file.
+#[cfg(test)]
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where P: AsRef<Path>, {
     let file = File::open(filename)?;
@@ -97,6 +144,14 @@ where P: AsRef<Path>, {
     Ok(lines)
 }
 
+/// Reads all lines of a log. Unlike read_lines, weirdly-encoded lines are kept (lossily), so
+/// that line numbers match the file.
+pub fn read_log_lines<P>(filename: P) -> io::Result<Vec<String>>
+where P: AsRef<Path>, {
+    let bytes = std::fs::read(filename)?;
+    Ok(String::from_utf8_lossy(&bytes).lines().map(|l| l.to_string()).collect())
+}
+
 fn regex_generator_helper(format: String) -> String {
     let splitters_re = Regex::new(r"(<[^<>]+>)").unwrap();
     let spaces_re = Regex::new(r" +").unwrap();
@@ -129,47 +184,264 @@ fn test_regex_generator_helper() {
     assert_eq!(regex_generator_helper(openstack_format), r"(?P<Logrecord>.*?)\s+(?P<Date>.*?)\s+(?P<Time>.*?)\s+(?P<Pid>.*?)\s+(?P<Level>.*?)\s+(?P<Component>.*?)\s+(\[(?P<ADDR>.*?)\])?\s+(?P<Content>.*?)");
 }
 
-/// Replaces provided (domain-specific) regexps with <*> in the log_line.
-fn apply_domain_specific_re(log_line: String, domain_specific_re:&Vec<Regex>) -> String {
-    let mut line = format!(" {}", log_line);
+/// A stretch of a line that a censoring regexp replaced: the placeholder it became, the
+/// original text, and the byte range of the placeholder in the censored line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CensoredValue {
+    pub placeholder: String,
+    pub value: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The placeholder a censoring regexp leaves behind: <NAME> if it is wrapped in a named group
+/// (?P<NAME>...), otherwise <*>.
+pub fn placeholder(domain_specific_re: &Regex) -> String {
+    match domain_specific_re.capture_names().flatten().next() {
+        Some(name) => format!("<{}>", name),
+        None => "<*>".to_string(),
+    }
+}
+
+// Byte ranges of `line` that a censoring regexp replaces: the named group if there is one,
+// with the text around it only giving context.
+fn censored_spans(s: &Regex, line: &str) -> Vec<(usize, usize)> {
+    let group = s.capture_names().flatten().next();
+    s.captures_iter(line).filter_map(|caps| match group {
+        Some(name) => caps.name(name),
+        None => caps.get(0),
+    }).map(|m| (m.start(), m.end())).collect()
+}
+
+/// Applies the censoring regexps in turn, like replace_all, but keeps track of what each
+/// placeholder replaced. Only the named group of a regexp is replaced, which lets a pattern
+/// require a delimiter before a value without censoring it. If a later regexp matches over an earlier placeholder, the two merge
+/// and the value is the original text of the whole match.
+pub fn censor(line: &str, domain_specific_re: &Vec<Regex>) -> (String, Vec<CensoredValue>) {
+    let mut line = line.to_string();
+    let mut values: Vec<CensoredValue> = vec![];
     for s in domain_specific_re {
-        line = s.replace_all(&line, "<*>").to_string();
+        let ph = placeholder(s);
+        let mut out = String::new();
+        let mut new_values = vec![];
+        let mut old = values.into_iter().peekable();
+        let mut last = 0;
+        for (m_start, m_end) in censored_spans(s, &line) {
+            // the rest of a match that started or ended inside an earlier placeholder went
+            // with that placeholder
+            if m_end <= last {
+                continue;
+            }
+            while let Some(v) = old.next_if(|v| v.end <= m_start) {
+                let start = out.len() + v.start - last;
+                new_values.push(CensoredValue { start, end: start + v.placeholder.len(), ..v });
+            }
+            // a match never splits a placeholder: it takes all of the ones it touches
+            let m_start = old.peek().map_or(m_start, |v| v.start.min(m_start)).max(last);
+            let mut value = String::new();
+            let mut pos = m_start;
+            while let Some(v) = old.next_if(|v| v.start < m_end) {
+                value.push_str(&line[pos..v.start]);
+                value.push_str(&v.value);
+                pos = v.end;
+            }
+            if pos < m_end {
+                value.push_str(&line[pos..m_end]);
+            }
+            out.push_str(&line[last..m_start]);
+            new_values.push(CensoredValue { placeholder: ph.clone(), value, start: out.len(), end: out.len() + ph.len() });
+            out.push_str(&ph);
+            last = m_end.max(pos);
+        }
+        for v in old {
+            let start = out.len() + v.start - last;
+            new_values.push(CensoredValue { start, end: start + v.placeholder.len(), ..v });
+        }
+        out.push_str(&line[last..]);
+        line = out;
+        values = new_values;
     }
-    return line;
+    (line, values)
+}
+
+#[test]
+fn test_censor() {
+    let line = " q2.34.4.5 Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; Fri Jun 17 20:55:07 2005 user unknown";
+    let (censored_line, _) = censor(line, &censored_regexps(&Linux));
+    assert_eq!(censored_line, " q<IP> Jun 14 <TIME> combo sshd(pam_unix)[19937]: check pass; <DATETIME> user unknown");
+    // HPC used to censor the = along with the number, giving (HWID<*>)
+    let (censored_line, values) = censor(" Component \\042alt0\\042 is in the unavailable state (HWID=3180)", &censored_regexps(&HPC));
+    assert_eq!(censored_line, " Component \\042alt0\\042 is in the unavailable state (HWID=<NUM>)");
+    assert_eq!(values.iter().map(|v| v.value.as_str()).collect::<Vec<_>>(), vec!["3180"]);
 }
 
 #[test]
-fn test_apply_domain_specific_re() {
-    let line = "q2.34.4.5 Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; Fri Jun 17 20:55:07 2005 user unknown".to_string();
-    let censored_line = apply_domain_specific_re(line, &censored_regexps(&Linux));
-    assert_eq!(censored_line, " q<*> Jun 14 <*> combo sshd(pam_unix)[19937]: check pass; <*> user unknown");
-}
-
-pub fn token_splitter(log_line: String, re:&Regex, domain_specific_re:&Vec<Regex>) -> Vec<String> {
-    if let Some(m) = re.captures(log_line.trim()) {
-        let message = m.name("Content").unwrap().as_str().to_string();
-        // println!("{}", &message);
-        let line = apply_domain_specific_re(message, domain_specific_re);
-        return line.trim().split_whitespace().map(|s| s.to_string()).collect();
+fn test_censor_values() {
+    let (line, values) = censor(" 10.0.0.1:80 and 10.0.0.2", &vec![Regex::new(r"(?P<IP>(\d+\.){3}\d+)").unwrap(), Regex::new(r"<IP>:\d+").unwrap()]);
+    assert_eq!(line, " <*> and <IP>");
+    assert_eq!(values, vec![
+        CensoredValue { placeholder: "<*>".to_string(), value: "10.0.0.1:80".to_string(), start: 1, end: 4 },
+        CensoredValue { placeholder: "<IP>".to_string(), value: "10.0.0.2".to_string(), start: 9, end: 13 },
+    ]);
+}
+
+#[test]
+fn test_censor_inside_placeholder() {
+    let ip = Regex::new(r"(?P<IP>(\d+\.){3}\d+)").unwrap();
+    // matches starting and ending inside <IP>
+    let (line, values) = censor(" 10.0.0.1:80 up", &vec![ip.clone(), Regex::new(r"IP>:\d+").unwrap()]);
+    assert_eq!(line, " <*> up");
+    assert_eq!(values, vec![CensoredValue { placeholder: "<*>".to_string(), value: "10.0.0.1:80".to_string(), start: 1, end: 4 }]);
+    let (line, values) = censor(" 5 10.0.0.1 up", &vec![ip, Regex::new(r"\d+ <I").unwrap()]);
+    assert_eq!(line, " <*> up");
+    assert_eq!(values, vec![CensoredValue { placeholder: "<*>".to_string(), value: "5 10.0.0.1".to_string(), start: 1, end: 4 }]);
+}
+
+pub fn token_splitter(log_line: String, config:&FormatConfig) -> Vec<String> {
+    token_splitter_with_values(log_line, config).0
+}
+
+/// Like token_splitter, but also returns what censoring replaced, in order of appearance.
+pub fn token_splitter_with_values(log_line: String, config:&FormatConfig) -> (Vec<String>, Vec<CensoredValue>) {
+    let (tokens, values, _) = token_splitter_with_spans(log_line, config);
+    (tokens, values)
+}
+
+/// Tokens, censored values and token spans, as returned by token_splitter_with_spans.
+pub type SplitLine = (Vec<String>, Vec<CensoredValue>, Vec<(usize, usize)>);
+
+/// Position in the uncensored line of `pos` in the censored one; a position inside a
+/// placeholder moves to the end of the value it replaced.
+pub fn uncensored_offset(pos: usize, values: &[CensoredValue]) -> usize {
+    let mut shift: isize = 0;
+    for v in values {
+        if v.end <= pos {
+            shift += v.value.len() as isize - v.placeholder.len() as isize;
+        } else if v.start < pos {
+            return (v.start as isize + shift) as usize + v.value.len();
+        } else {
+            break;
+        }
+    }
+    (pos as isize + shift) as usize
+}
+
+/// Replaces the tokens at `spans` of the censored `line` whose class is in `classes` by the
+/// class placeholder, and returns the new line, censored values and token spans.
+fn censor_classes(line: String, values: Vec<CensoredValue>, spans: Vec<(usize, usize)>, classes: &[TokenClass]) -> (String, Vec<CensoredValue>, Vec<(usize, usize)>) {
+    if classes.is_empty() {
+        return (line, values, spans);
+    }
+    let mut out = String::new();
+    let mut new_values = vec![];
+    let mut new_spans = vec![];
+    let mut old = values.into_iter().peekable();
+    let mut last = 0;
+    for (start, end) in spans {
+        out.push_str(&line[last..start]);
+        let token_start = out.len();
+        // a token holding a placeholder is never a number or an id, so values never straddle
+        // a censored token
+        let class = classify(&line[start..end]);
+        let censored = classes.contains(&class);
+        let before = if censored { start } else { end };
+        while let Some(v) = old.next_if(|v| v.start < before) {
+            let v_start = v.start + token_start - start;
+            new_values.push(CensoredValue { start: v_start, end: v_start + v.placeholder.len(), ..v });
+        }
+        if censored {
+            let ph = format!("<{}>", class.name());
+            new_values.push(CensoredValue { placeholder: ph.clone(), value: line[start..end].to_string(), start: token_start, end: token_start + ph.len() });
+            out.push_str(&ph);
+        } else {
+            out.push_str(&line[start..end]);
+        }
+        new_spans.push((token_start, out.len()));
+        last = end;
+    }
+    for v in old {
+        let v_start = v.start + out.len() - last;
+        new_values.push(CensoredValue { start: v_start, end: v_start + v.placeholder.len(), ..v });
+    }
+    out.push_str(&line[last..]);
+    (out, new_values, new_spans)
+}
+
+/// Like token_splitter_with_values, but also returns the byte range of each token in
+/// log_line itself. A placeholder token covers the text it replaced.
+pub fn token_splitter_with_spans(log_line: String, config:&FormatConfig) -> SplitLine {
+    let indent = log_line.len() - log_line.trim_start().len();
+    if let Some(m) = config.re.captures(log_line.trim()) {
+        let message = m.name("Content").unwrap();
+        let (line, values) = censor(&format!(" {}", message.as_str()), &config.censors);
+        let spans = config.tokenizer.spans(&line);
+        let (line, values, spans) = censor_classes(line, values, spans, &config.classes);
+        // minus the space censoring is done with
+        let raw = |pos| indent + message.start() + uncensored_offset(pos, &values) - 1;
+        let raw_spans = spans.iter().map(|(start, end)| (raw(*start), raw(*end))).collect();
+        (spans.into_iter().map(|(start, end)| line[start..end].to_string()).collect(), values, raw_spans)
     } else {
-        return vec![];
+        (vec![], vec![], vec![])
     }
 }
 
 #[test]
 fn test_token_splitter() {
     let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
-    let re = regex_generator(format_string(&Linux));
-    let split_line = token_splitter(line, &re, &censored_regexps(&Linux));
+    let split_line = token_splitter(line, &FormatConfig::new(&Linux));
     assert_eq!(split_line, vec!["check", "pass;", "user", "unknown"]);
 }
 
+#[test]
+fn test_split_punctuation() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
+    let split_line = token_splitter(line.clone(), &FormatConfig::split_punctuation(&Linux));
+    assert_eq!(split_line, vec!["check", "pass", "user", "unknown"]);
+    let mut dbl = HashMap::new();
+    let mut trpl = HashMap::new();
+    let mut all_token_list = vec![];
+    process_dictionary_builder_line(line, None, &FormatConfig::split_punctuation(&Linux), &mut dbl, &mut trpl, &mut all_token_list, None, None);
+    assert_eq!(dbl.keys().collect::<BTreeSet<_>>(), BTreeSet::from([&"check^pass".to_string(), &"pass^user".to_string(), &"user^unknown".to_string()]));
+}
+
+#[test]
+fn test_token_classes() {
+    let line = "081109 204005 35 INFO dfs.FSNamesystem: BLOCK* blockMap updated: 10.251.73.220:50010 is added to blk_-712 size 67108864 v2 -1".to_string();
+    let (tokens, values, spans) = token_splitter_with_spans(line.clone(), &FormatConfig::new(&HDFS));
+    assert_eq!(tokens, vec!["BLOCK*", "blockMap", "updated:", "<IP>", "is", "added", "to", "<BLK>", "size", "<INT>", "v2", "<INT>"]);
+    assert_eq!(values.iter().map(|v| v.value.as_str()).collect::<Vec<_>>(), vec!["10.251.73.220:50010", "blk_-712", "67108864", "-1"]);
+    let texts: Vec<&str> = spans.iter().map(|(start, end)| &line[*start..*end]).collect();
+    assert_eq!(texts[9..], ["67108864", "v2", "-1"]);
+}
+
+#[test]
+fn test_token_spans() {
+    let line = "Jun 15 02:04:59 combo sshd(pam_unix)[20884]: failure; rhost=220.135.151.1  user=root at 02:04:59".to_string();
+    let (tokens, _, spans) = token_splitter_with_spans(line.clone(), &FormatConfig::split_punctuation(&Linux));
+    assert_eq!(tokens, vec!["failure", "rhost=", "<IP>", "user=", "root", "at", "<TIME>"]);
+    let texts: Vec<&str> = spans.iter().map(|(start, end)| &line[*start..*end]).collect();
+    assert_eq!(texts, vec!["failure", "rhost=", "220.135.151.1", "user=", "root", "at", "02:04:59"]);
+}
+
+/// Returns the date/time header fields of log_line (whichever of Month, Date and Time the format has), space-separated.
+pub fn header_time(log_line: &str, re:&Regex) -> Option<String> {
+    let m = re.captures(log_line.trim())?;
+    let fields: Vec<&str> = ["Month", "Date", "Time"].iter().filter_map(|f| m.name(f)).map(|f| f.as_str()).collect();
+    if fields.is_empty() { None } else { Some(fields.join(" ")) }
+}
+
+#[test]
+fn test_header_time() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown";
+    assert_eq!(header_time(line, &regex_generator(format_string(&Linux))), Some("Jun 14 15:16:02".to_string()));
+}
+
 // processes line, adding to the end of line the first two tokens from lookahead_line, and returns the first 2 tokens on this line
-fn process_dictionary_builder_line(line: String, lookahead_line: Option<String>, regexp:&Regex, regexps:&Vec<Regex>, dbl: &mut HashMap<String, i32>, trpl: &mut HashMap<String, i32>, all_token_list: &mut Vec<String>, prev1: Option<String>, prev2: Option<String>) -> (Option<String>, Option<String>) {
+pub fn process_dictionary_builder_line(line: String, lookahead_line: Option<String>, config:&FormatConfig, dbl: &mut HashMap<String, i32>, trpl: &mut HashMap<String, i32>, all_token_list: &mut Vec<String>, prev1: Option<String>, prev2: Option<String>) -> (Option<String>, Option<String>) {
     let (next1, next2) = match lookahead_line {
         None => (None, None),
         Some(ll) => {
-            let next_tokens = token_splitter(ll, &regexp, &regexps);
+            let next_tokens = token_splitter(ll, config);
             match next_tokens.len() {
                 0 => (None, None),
                 1 => (Some(next_tokens[0].clone()), None),
@@ -178,7 +450,7 @@ fn process_dictionary_builder_line(line: String, lookahead_line: Option<String>,
         }
     };
 
-    let mut tokens = token_splitter(line, &regexp, &regexps);
+    let mut tokens = token_splitter(line, config);
     if tokens.is_empty() {
         return (None, None);
     }
@@ -225,8 +497,8 @@ fn process_dictionary_builder_line(line: String, lookahead_line: Option<String>,
 }
 
 // processes line, adding to the end of line the first two tokens from lookahead_line, and returns the first 2 tokens on this line
-fn get_prev_values(line: String, regexp:&Regex, regexps:&Vec<Regex>) -> (Option<String>, Option<String>) {
-    let mut tokens = token_splitter(line, &regexp, &regexps);
+fn get_prev_values(line: String, config:&FormatConfig) -> (Option<String>, Option<String>) {
+    let mut tokens = token_splitter(line, config);
     if tokens.is_empty() {
         return (None, None);
     }
@@ -245,11 +517,11 @@ fn get_prev_values(line: String, regexp:&Regex, regexps:&Vec<Regex>) -> (Option<
 }
 
 // processes line, adding to the end of line the first two tokens from lookahead_line, and returns the first 2 tokens on this line
-fn process_dictionary_builder_line_2(line: String, lookahead_line: Option<String>, regexp:&Regex, regexps:&Vec<Regex>, dbl: &mut Arc<DashMap<String, i32>>, trpl: &mut Arc<DashMap<String, i32>>, all_token_list: &mut Vec<String>, prev1: Option<String>, prev2: Option<String>) -> (Option<String>, Option<String>) {
+fn process_dictionary_builder_line_2(line: String, lookahead_line: Option<String>, config:&FormatConfig, dbl: &mut Arc<DashMap<String, i32>>, trpl: &mut Arc<DashMap<String, i32>>, all_token_list: &mut Vec<String>, prev1: Option<String>, prev2: Option<String>) -> (Option<String>, Option<String>) {
     let (next1, next2) = match lookahead_line {
         None => (None, None),
         Some(ll) => {
-            let next_tokens = token_splitter(ll, &regexp, &regexps);
+            let next_tokens = token_splitter(ll, config);
             match next_tokens.len() {
                 0 => (None, None),
                 1 => (Some(next_tokens[0].clone()), None),
@@ -258,7 +530,7 @@ fn process_dictionary_builder_line_2(line: String, lookahead_line: Option<String
         }
     };
 
-    let mut tokens = token_splitter(line, &regexp, &regexps);
+    let mut tokens = token_splitter(line, config);
     if tokens.is_empty() {
         return (None, None);
     }
@@ -304,14 +576,13 @@ fn process_dictionary_builder_line_2(line: String, lookahead_line: Option<String
     return (last1, last2);
 }
 
-fn parallelized_dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+fn parallelized_dictionary_builder(lines: Vec<String>, config: FormatConfig, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
     let mut combined_dbl = HashMap::new();
     let mut combined_trpl = HashMap::new();
     let mut combined_all_token_list = vec![];
-    let regex = Arc::new(regex_generator(format));
-    let regexps = Arc::new(regexps);
+    let config = Arc::new(config);
 
-    if let Ok(lines) = read_lines_2(raw_fn) {
+    if !lines.is_empty() {
         
         let mut chunks: Vec<Vec<String>> =  Vec::new();
         let mut chunk: Vec<String> = Vec::new();
@@ -372,13 +643,12 @@ fn parallelized_dictionary_builder(raw_fn: String, format: String, regexps: Vec<
         let mut thread_number = 1;
         for chunk in chunks {
             let handle = thread::spawn({
-                let regex = Arc::clone(&regex);
-                let regexps = Arc::clone(&regexps);
+                let config = Arc::clone(&config);
                 move || {
                 let mut prev1 = None; let mut prev2 = None;
                 let mut chunk = chunk;
                 if thread_number != 1 {
-                    (prev1, prev2) = get_prev_values(chunk[0].to_string(), &regex, &regexps);
+                    (prev1, prev2) = get_prev_values(chunk[0].to_string(), &config);
                     chunk.remove(0);
                 }
                 let mut dbl = HashMap::new();
@@ -393,9 +663,9 @@ fn parallelized_dictionary_builder(raw_fn: String, format: String, regexps: Vec<
 
                     if x < chunk_size {
                         if x+1 < chunk.len() {
-                            (prev1, prev2) = process_dictionary_builder_line(chunk[x].to_string(), Some(chunk[x+1].to_string()), &regex, &regexps, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2)
+                            (prev1, prev2) = process_dictionary_builder_line(chunk[x].to_string(), Some(chunk[x+1].to_string()), &config, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2)
                         } else {
-                            (prev1, prev2) = process_dictionary_builder_line(chunk[x].to_string(), None, &regex, &regexps, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2);
+                            (prev1, prev2) = process_dictionary_builder_line(chunk[x].to_string(), None, &config, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2);
                         }
                     }
                 }
@@ -424,14 +694,13 @@ fn parallelized_dictionary_builder(raw_fn: String, format: String, regexps: Vec<
     return (combined_dbl, combined_trpl, combined_all_token_list)
 }
 
-fn concurrent_map_dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+fn concurrent_map_dictionary_builder(lines: Vec<String>, config: FormatConfig, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
     let mut combined_dbl = Arc::new(DashMap::new());
     let mut combined_trpl = Arc::new(DashMap::new());
     let mut combined_all_token_list = vec![];
-    let regex = Arc::new(regex_generator(format));
-    let regexps = Arc::new(regexps);
+    let config = Arc::new(config);
 
-    if let Ok(lines) = read_lines_2(raw_fn) {
+    if !lines.is_empty() {
         
         let mut chunks: Vec<Vec<String>> =  Vec::new();
         let mut chunk: Vec<String> = Vec::new();
@@ -494,15 +763,14 @@ fn concurrent_map_dictionary_builder(raw_fn: String, format: String, regexps: Ve
             // let mut combined_dbl_ref = combined_dbl.clone();
             // let mut combined_trpl_ref = combined_trpl.clone();
             let handle = thread::spawn({
-                let regex = Arc::clone(&regex);
-                let regexps = Arc::clone(&regexps);
+                let config = Arc::clone(&config);
                 let mut combined_dbl_ref = Arc::clone(&combined_dbl);
                 let mut combined_trpl_ref = Arc::clone(&combined_trpl);
                 move || {
                 let mut prev1 = None; let mut prev2 = None;
                 let mut chunk = chunk;
                 if thread_number != 1 {
-                    (prev1, prev2) = get_prev_values(chunk[0].to_string(), &regex, &regexps);
+                    (prev1, prev2) = get_prev_values(chunk[0].to_string(), &config);
                     chunk.remove(0);
                 }
                 let mut all_token_list = vec![];
@@ -515,9 +783,9 @@ fn concurrent_map_dictionary_builder(raw_fn: String, format: String, regexps: Ve
 
                     if x < chunk_size {
                         if x+1 < chunk.len() {
-                            (prev1, prev2) = process_dictionary_builder_line_2(chunk[x].to_string(), Some(chunk[x+1].to_string()), &regex, &regexps, &mut combined_dbl_ref, &mut combined_trpl_ref, &mut all_token_list, prev1, prev2)
+                            (prev1, prev2) = process_dictionary_builder_line_2(chunk[x].to_string(), Some(chunk[x+1].to_string()), &config, &mut combined_dbl_ref, &mut combined_trpl_ref, &mut all_token_list, prev1, prev2)
                         } else {
-                            (prev1, prev2) = process_dictionary_builder_line_2(chunk[x].to_string(), None, &regex, &regexps, &mut combined_dbl_ref, &mut combined_trpl_ref, &mut all_token_list, prev1, prev2);
+                            (prev1, prev2) = process_dictionary_builder_line_2(chunk[x].to_string(), None, &config, &mut combined_dbl_ref, &mut combined_trpl_ref, &mut all_token_list, prev1, prev2);
                         }
                     }
                 }
@@ -550,11 +818,12 @@ fn concurrent_map_dictionary_builder(raw_fn: String, format: String, regexps: Ve
     return (combined_dbl, combined_trpl, combined_all_token_list)
 }
 
-fn dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+// the sequential builder, which the tests check the others against
+#[cfg(test)]
+pub(crate) fn dictionary_builder(raw_fn: String, config: FormatConfig) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
     let mut dbl = HashMap::new();
     let mut trpl = HashMap::new();
     let mut all_token_list = vec![];
-    let regex = regex_generator(format);
 
     let mut prev1 = None; let mut prev2 = None;
 
@@ -566,9 +835,9 @@ fn dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>) -> (H
                 Some(Ok(ip)) =>
                     match lp.peek() {
                         None =>
-                            (prev1, prev2) = process_dictionary_builder_line(ip, None, &regex, &regexps, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2),
+                            (prev1, prev2) = process_dictionary_builder_line(ip, None, &config, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2),
                         Some(Ok(next_line)) =>
-                            (prev1, prev2) = process_dictionary_builder_line(ip, Some(next_line.clone()), &regex, &regexps, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2),
+                            (prev1, prev2) = process_dictionary_builder_line(ip, Some(next_line.clone()), &config, &mut dbl, &mut trpl, &mut all_token_list, prev1, prev2),
                         Some(Err(_)) => {} // meh, some weirdly-encoded line, throw it out
                     }
                 Some(Err(_)) => {} // meh, some weirdly-encoded line, throw it out
@@ -581,11 +850,11 @@ fn dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>) -> (H
 #[test]
 fn test_dictionary_builder_process_line_lookahead_is_none() {
     let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
-    let re = regex_generator(format_string(&Linux));
+    let config = FormatConfig::new(&Linux);
     let mut dbl = HashMap::new();
     let mut trpl = HashMap::new();
     let mut all_token_list = vec![];
-    let (last1, last2) = process_dictionary_builder_line(line, None, &re, &censored_regexps(&Linux), &mut dbl, &mut trpl, &mut all_token_list, None, None);
+    let (last1, last2) = process_dictionary_builder_line(line, None, &config, &mut dbl, &mut trpl, &mut all_token_list, None, None);
     assert_eq!((last1, last2), (Some("unknown".to_string()), Some("user".to_string())));
 
     let mut dbl_oracle = HashMap::new();
@@ -604,11 +873,11 @@ fn test_dictionary_builder_process_line_lookahead_is_none() {
 fn test_dictionary_builder_process_line_lookahead_is_some() {
     let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
     let next_line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: baz bad".to_string();
-    let re = regex_generator(format_string(&Linux));
+    let config = FormatConfig::new(&Linux);
     let mut dbl = HashMap::new();
     let mut trpl = HashMap::new();
     let mut all_token_list = vec![];
-    let (last1, last2) = process_dictionary_builder_line(line, Some(next_line), &re, &censored_regexps(&Linux), &mut dbl, &mut trpl, &mut all_token_list, Some("foo".to_string()), Some("bar".to_string()));
+    let (last1, last2) = process_dictionary_builder_line(line, Some(next_line), &config, &mut dbl, &mut trpl, &mut all_token_list, Some("foo".to_string()), Some("bar".to_string()));
     assert_eq!((last1, last2), (Some("unknown".to_string()), Some("user".to_string())));
 
     let mut dbl_oracle = HashMap::new();
@@ -629,7 +898,14 @@ fn test_dictionary_builder_process_line_lookahead_is_some() {
     assert_eq!(trpl, trpl_oracle);
 }
 
-pub fn parse_raw(raw_fn: String, lf:&LogFormat, is_single_map: bool, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+pub fn parse_raw(raw_fn: String, config: &FormatConfig, is_single_map: bool, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+    parse_lines(read_lines_2(raw_fn).unwrap_or_default(), config, is_single_map, num_of_threads)
+}
+
+/// Like parse_raw, for lines already read, e.g. those inside --since/--until.
+pub fn parse_lines(lines: Vec<String>, config: &FormatConfig, is_single_map: bool, num_of_threads: usize) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
+    // every thread needs a line of its own, or the chunking drops them all
+    let num_of_threads = num_of_threads.min(lines.len()).max(1);
     let mut double_dict = HashMap::new();
     let mut triple_dict = HashMap::new();
     let mut all_token_list = vec![];
@@ -639,16 +915,16 @@ pub fn parse_raw(raw_fn: String, lf:&LogFormat, is_single_map: bool, num_of_thre
 
     if is_single_map {
         println!("Seperate Mapping Parallel Dictionary Builder");
-        (double_dict, triple_dict, all_token_list) = parallelized_dictionary_builder(raw_fn, format_string(&lf), censored_regexps(&lf), num_of_threads);
+        (double_dict, triple_dict, all_token_list) = parallelized_dictionary_builder(lines, config.clone(), num_of_threads);
         println!("-------------------");
         println!("Seperate Mapping Duration: {:?}", start_time.elapsed());
         println!("-------------------");
     } else {
         // println!("Sequential Route");
-        // (double_dict, triple_dict, all_token_list) = dictionary_builder(raw_fn, format_string(&lf), censored_regexps(&lf));
+        // (double_dict, triple_dict, all_token_list) = dictionary_builder(raw_fn, FormatConfig::new(lf));
 
         println!("Concurrent Mapping Parallel Dictionary Builder");
-        (double_dict, triple_dict, all_token_list) = concurrent_map_dictionary_builder(raw_fn, format_string(&lf), censored_regexps(&lf), num_of_threads);
+        (double_dict, triple_dict, all_token_list) = concurrent_map_dictionary_builder(lines, config.clone(), num_of_threads);
         println!("-------------------");
         println!("Concurrent Mapping Duration: {:?}", start_time.elapsed());
         println!("-------------------");
@@ -659,7 +935,7 @@ pub fn parse_raw(raw_fn: String, lf:&LogFormat, is_single_map: bool, num_of_thre
 
 #[test]
 fn test_parse_raw_linux() {
-    let (double_dict, triple_dict, all_token_list) = parse_raw("data/from_paper.log".to_string(), &Linux, false, 8);
+    let (double_dict, triple_dict, all_token_list) = parse_raw("data/from_paper.log".to_string(), &FormatConfig::new(&Linux), false, 8);
     let all_token_list_oracle = vec![
         "hdfs://hostname/2kSOSP.log:21876+7292".to_string(),
         "hdfs://hostname/2kSOSP.log:14584+7292".to_string(),
@@ -0,0 +1,175 @@
+use regex::Regex;
+
+// Censoring patterns that any format can use by name. Each one wraps the censored part in a
+// group named after the pattern, so it leaves a typed placeholder such as <UUID>; variants
+// such as IP_LIST are named after what comes before the underscore and leave <IP>. Rust regexes
+// have no lookbehind, so patterns that must not start in the middle of a word match the
+// delimiter before the value outside the group; censor() keeps that delimiter.
+
+/// Names of the built-in patterns, in the order they are best applied: URLs and emails
+/// before the paths and hosts inside them, specific number shapes before NUM.
+pub const PATTERN_NAMES: [&str; 18] = [
+    "URL", "EMAIL", "UUID", "MAC", "IPV6", "IPV4", "IP", "IP_LIST", "IP_PORT", "HOST", "HOST_PORT",
+    "ISOTIME", "PATH", "EPOCH", "HEX", "DURATION", "SIZE", "NUM",
+];
+
+const HEX4: &str = "[0-9a-fA-F]{1,4}";
+
+fn source(name: &str) -> Option<String> {
+    Some(match name {
+        "URL" => r#"\b(?P<URL>[a-zA-Z][a-zA-Z0-9+.-]*://[^\s"'<>]+)"#.to_string(),
+        "EMAIL" => r"\b(?P<EMAIL>[\w.+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+)\b".to_string(),
+        "UUID" => r"\b(?P<UUID>[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12})\b".to_string(),
+        "MAC" => r"\b(?P<MAC>[0-9a-fA-F]{2}(:[0-9a-fA-F]{2}){5}|[0-9a-fA-F]{2}(-[0-9a-fA-F]{2}){5})\b".to_string(),
+        // full form, or compressed with "::"; bracketed when followed by a port
+        "IPV6" => {
+            let addr = format!("({h}:){{7}}{h}|({h}:){{1,7}}:({h}(:{h}){{0,5}})?|::({h}(:{h}){{0,6}})?", h = HEX4);
+            format!(r"(^|[^\w:])(?P<IPV6>\[({a})\](:\d{{1,5}})?|{a})", a = addr)
+        }
+        "IPV4" => r"\b(?P<IPV4>(\d{1,3}\.){3}\d{1,3}(:\d{1,5})?)\b".to_string(),
+        // looser than IPV4: any four dotted numbers, even glued to a word
+        "IP" => r"(?P<IP>(\d+\.){3}\d+)".to_string(),
+        // a comma-separated run of addresses, as one value
+        "IP_LIST" => r"(?P<IP>((\d+\.){3}\d+,?)+)".to_string(),
+        // with the slash before and the port and colon after, as in /10.251.73.220:50010:
+        "IP_PORT" => r"(?P<IP>(/|)([0-9]+\.){3}[0-9]+(:[0-9]+|)(:|))".to_string(),
+        // at least three dotted labels, so that names like dfs.DataNode stay
+        "HOST" => r"(?P<HOST>([\w-]+\.){2,}[\w-]+)".to_string(),
+        // two dotted labels are enough when a port may follow
+        "HOST_PORT" => r"(?P<HOST>([\w-]+\.)+[\w-]+(:\d+)?)".to_string(),
+        "ISOTIME" => r"\b(?P<ISOTIME>\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}(:\d{2}([.,]\d+)?)?(Z|[+-]\d{2}:?\d{2})?)\b".to_string(),
+        "PATH" => r#"(^|[\s=:(\["',])(?P<PATH>~?(/[\w.@+-]+)+/?)"#.to_string(),
+        // seconds (2001-2033) or milliseconds since 1970
+        "EPOCH" => r"(^|[^\w.])(?P<EPOCH>1\d{9}(\d{3}|\.\d{1,6})?)\b".to_string(),
+        "HEX" => r"\b(?P<HEX>0[xX][0-9a-fA-F]+|[0-9a-fA-F]{8,})\b".to_string(),
+        "DURATION" => r"\b(?P<DURATION>\d+(\.\d+)?\s?(ns|us|µs|ms|s|secs?|seconds?|mins?|minutes?|h|hrs?|hours?|days?))\b".to_string(),
+        "SIZE" => r"\b(?P<SIZE>\d+(\.\d+)?\s?([kKMGTPE]i?B|B|bytes?))\b".to_string(),
+        // (?<=[^A-Za-z0-9])[-+]?\d+ without the lookbehind
+        "NUM" => r"(^|[^A-Za-z0-9])(?P<NUM>[-+]?\d+(\.\d+)?)\b".to_string(),
+        _ => return None,
+    })
+}
+
+/// The built-in pattern called `name`, if there is one.
+pub fn pattern(name: &str) -> Option<Regex> {
+    source(name).map(|s| Regex::new(&s).unwrap())
+}
+
+/// The named built-in patterns, in the order given; panics on an unknown name.
+pub fn patterns(names: &[&str]) -> Vec<Regex> {
+    names.iter().map(|n| pattern(n).unwrap_or_else(|| panic!("no censoring pattern named {}, try one of {}", n, PATTERN_NAMES.join(", ")))).collect()
+}
+
+#[cfg(test)]
+fn censored(name: &str, line: &str) -> String {
+    crate::packages::parser::censor(line, &patterns(&[name])).0
+}
+
+#[test]
+fn test_all_patterns_compile() {
+    assert_eq!(patterns(&PATTERN_NAMES).len(), PATTERN_NAMES.len());
+    assert!(pattern("NOPE").is_none());
+}
+
+#[test]
+fn test_url_pattern() {
+    assert_eq!(censored("URL", "GET https://example.com/a?b=1 done"), "GET <URL> done");
+    assert_eq!(censored("URL", "see \"ftp://h/x\""), "see \"<URL>\"");
+}
+
+#[test]
+fn test_email_pattern() {
+    assert_eq!(censored("EMAIL", "mail from john.doe+x@mail.example.org, ok"), "mail from <EMAIL>, ok");
+    assert_eq!(censored("EMAIL", "user@localhost"), "user@localhost");
+}
+
+#[test]
+fn test_uuid_pattern() {
+    assert_eq!(censored("UUID", "req 123e4567-e89b-12d3-a456-426614174000 failed"), "req <UUID> failed");
+    assert_eq!(censored("UUID", "123e4567-e89b-12d3-a456"), "123e4567-e89b-12d3-a456");
+}
+
+#[test]
+fn test_mac_pattern() {
+    assert_eq!(censored("MAC", "eth0 00:1A:2b:3c:4D:5e up"), "eth0 <MAC> up");
+    assert_eq!(censored("MAC", "hw 00-1a-2b-3c-4d-5e"), "hw <MAC>");
+    // mixed separators are not a MAC
+    assert_eq!(censored("MAC", "00:1a-2b:3c:4d:5e"), "00:1a-2b:3c:4d:5e");
+}
+
+#[test]
+fn test_ipv6_pattern() {
+    assert_eq!(censored("IPV6", "from 2001:0db8:85a3:0000:0000:8a2e:0370:7334 ok"), "from <IPV6> ok");
+    assert_eq!(censored("IPV6", "from fe80::1ff:fe23:4567:890a ok"), "from <IPV6> ok");
+    assert_eq!(censored("IPV6", "bind ::1"), "bind <IPV6>");
+    assert_eq!(censored("IPV6", "to [2001:db8::1]:8080"), "to <IPV6>");
+    // times have neither eight groups nor "::"
+    assert_eq!(censored("IPV6", "at 12:30:45"), "at 12:30:45");
+}
+
+#[test]
+fn test_ipv4_pattern() {
+    assert_eq!(censored("IPV4", "rhost=10.0.0.1 port 22"), "rhost=<IPV4> port 22");
+    assert_eq!(censored("IPV4", "to 192.168.1.20:50010"), "to <IPV4>");
+    assert_eq!(censored("IPV4", "version 1.2.3"), "version 1.2.3");
+}
+
+#[test]
+fn test_ip_and_host_patterns() {
+    assert_eq!(censored("IP", "q2.34.4.5 up"), "q<IP> up");
+    assert_eq!(censored("IP_LIST", "from 10.0.0.1,10.0.0.2 ok"), "from <IP> ok");
+    assert_eq!(censored("IP_PORT", "to /10.251.73.220:50010: done"), "to <IP> done");
+    assert_eq!(censored("HOST", "at spark-1.example.com and dfs.DataNode"), "at <HOST> and dfs.DataNode");
+    assert_eq!(censored("HOST_PORT", "open proxy.cse.cuhk.edu.hk:5070 and qq.com"), "open <HOST> and <HOST>");
+}
+
+#[test]
+fn test_isotime_pattern() {
+    assert_eq!(censored("ISOTIME", "at 2017-12-23T22:15:29.606Z done"), "at <ISOTIME> done");
+    assert_eq!(censored("ISOTIME", "at 2017-12-23 22:15:29+01:00"), "at <ISOTIME>");
+    assert_eq!(censored("ISOTIME", "day 2017-12-23"), "day 2017-12-23");
+}
+
+#[test]
+fn test_path_pattern() {
+    assert_eq!(censored("PATH", "open /var/log/syslog.1 failed"), "open <PATH> failed");
+    assert_eq!(censored("PATH", "file=/tmp/x, dir=~/cache/"), "file=<PATH>, dir=<PATH>");
+    // a slash inside a word is not a path
+    assert_eq!(censored("PATH", "read 1/2 and a/b"), "read 1/2 and a/b");
+}
+
+#[test]
+fn test_epoch_pattern() {
+    assert_eq!(censored("EPOCH", "onExtend:1514038530000 14"), "onExtend:<EPOCH> 14");
+    assert_eq!(censored("EPOCH", "t=1514038530.25 n=2147483647"), "t=<EPOCH> n=2147483647");
+    assert_eq!(censored("EPOCH", "id 11514038530"), "id 11514038530");
+}
+
+#[test]
+fn test_hex_pattern() {
+    assert_eq!(censored("HEX", "addr 0x7fff5fbff8a0 tid 3fa9c2e1"), "addr <HEX> tid <HEX>");
+    assert_eq!(censored("HEX", "code 0x1F"), "code <HEX>");
+    assert_eq!(censored("HEX", "face cafe 12ab"), "face cafe 12ab");
+}
+
+#[test]
+fn test_duration_pattern() {
+    assert_eq!(censored("DURATION", "took 15ms, then 2.5 seconds"), "took <DURATION>, then <DURATION>");
+    assert_eq!(censored("DURATION", "3 hours later"), "<DURATION> later");
+    assert_eq!(censored("DURATION", "10 sheep"), "10 sheep");
+}
+
+#[test]
+fn test_size_pattern() {
+    assert_eq!(censored("SIZE", "stored 12.3 KB, free 4GiB"), "stored <SIZE>, free <SIZE>");
+    assert_eq!(censored("SIZE", "read 512 bytes"), "read <SIZE>");
+    assert_eq!(censored("SIZE", "12 Bits"), "12 Bits");
+}
+
+#[test]
+fn test_num_pattern() {
+    assert_eq!(censored("NUM", "5 of -12, +3 and 0.75"), "<NUM> of <NUM>, <NUM> and <NUM>");
+    assert_eq!(censored("NUM", "size=42 blk_-160899"), "size=<NUM> blk_<NUM>");
+    // digits inside words are left alone
+    assert_eq!(censored("NUM", "ipv4 x86 12ab"), "ipv4 x86 12ab");
+}
@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::packages::template::Record;
+
+// A post-pass over any parser's templates that looks at what their positions actually hold.
+// The n-gram cutoff decides token by token, so it can mark a token dynamic that only ever takes
+// two values, or keep an identifier static because each value repeats often enough, as in
+// data/from_paper.log with a low cutoff: one template per block id. Refining first turns <*>
+// positions with very few values back into static tokens, then merges templates that differ at
+// a single position holding many values.
+
+/// `split_max`: a <*> with at most this many distinct values becomes static, if the template
+/// covers at least twice as many lines as there are values (a template seen once proves
+/// nothing). `merge_min`: templates that are the same but for one position, which holds at
+/// least this many distinct values over their lines, become one template with a <*> there.
+/// Keep split_max below merge_min, or the second step undoes the first.
+pub fn refine(records: &mut [Record], split_max: usize, merge_min: usize) {
+    split_low_cardinality(records, split_max);
+    merge_high_cardinality(records, merge_min);
+}
+
+/// The records of each template, by their indices.
+fn by_template(records: &[Record]) -> HashMap<String, Vec<usize>> {
+    let mut templates: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, r) in records.iter().enumerate().filter(|(_, r)| !r.tokens.is_empty()) {
+        templates.entry(r.template()).or_default().push(i);
+    }
+    templates
+}
+
+fn split_low_cardinality(records: &mut [Record], split_max: usize) {
+    for lines in by_template(records).values() {
+        for position in records[lines[0]].dynamic.clone() {
+            let values: HashSet<&str> = lines.iter().map(|l| records[*l].tokens[position].as_str()).collect();
+            if values.len() <= split_max && lines.len() >= 2 * values.len() {
+                for l in lines {
+                    records[*l].dynamic.retain(|d| *d != position);
+                }
+            }
+        }
+    }
+}
+
+fn merge_high_cardinality(records: &mut [Record], merge_min: usize) {
+    let longest = records.iter().map(|r| r.tokens.len()).max().unwrap_or(0);
+    for position in 0..longest {
+        // templates with `position` wildcarded, and the lines under each
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for lines in by_template(records).into_values() {
+            let r = &records[lines[0]];
+            if r.tokens.len() <= position || r.dynamic.contains(&position) {
+                continue;
+            }
+            // a template needs something static left besides the merged position
+            if (0..r.tokens.len()).all(|i| i == position || r.dynamic.contains(&i)) {
+                continue;
+            }
+            let key: Vec<&str> = r.tokens.iter().enumerate()
+                .map(|(i, t)| if i == position || r.dynamic.contains(&i) { "<*>" } else { t.as_str() })
+                .collect();
+            groups.entry(key.join(" ")).or_default().extend(lines);
+        }
+        for lines in groups.values() {
+            let values: HashSet<&str> = lines.iter().map(|l| records[*l].tokens[position].as_str()).collect();
+            if values.len() >= merge_min {
+                for l in lines {
+                    let dynamic = &mut records[*l].dynamic;
+                    dynamic.push(position);
+                    dynamic.sort_unstable();
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_refine_from_paper() {
+    use crate::LogFormat::Spark;
+    use crate::packages::log_parser::{template_counts, LogParser, NgramParser};
+    use crate::packages::parser::{read_log_lines, FormatConfig};
+
+    // with a cutoff of 1 every line is its own template
+    let lines = read_log_lines("data/from_paper.log").unwrap();
+    let mut parser = NgramParser::new(&FormatConfig::new(&Spark), 1);
+    parser.train(&lines);
+    let mut records = parser.parse(&lines);
+    assert_eq!(template_counts(&records).len(), 9);
+
+    refine(&mut records, 2, 4);
+    let templates: Vec<String> = template_counts(&records).into_iter().map(|(t, _)| t).collect();
+    assert_eq!(templates, vec!["Input split: <*>", "Found block <*> locally"]);
+}
+
+#[test]
+fn test_refine_splits_small_enums() {
+    let record = |tokens: &[&str], dynamic: Vec<usize>| Record {
+        line_no: 0, raw: String::new(), tokens: tokens.iter().map(|t| t.to_string()).collect(),
+        dynamic, censored: vec![], spans: vec![], timestamp: None,
+    };
+    let mut records = vec![
+        record(&["link", "up", "on", "eth0"], vec![1, 3]),
+        record(&["link", "down", "on", "eth1"], vec![1, 3]),
+        record(&["link", "up", "on", "eth2"], vec![1, 3]),
+        record(&["link", "down", "on", "eth3"], vec![1, 3]),
+        record(&["user", "bob"], vec![1]),
+    ];
+    refine(&mut records, 2, 4);
+    let templates: Vec<String> = records.iter().map(|r| r.template()).collect();
+    assert_eq!(templates[..2], ["link up on <*>", "link down on <*>"]);
+    // a single line is not enough to call its value static
+    assert_eq!(templates[4], "user <*>");
+}
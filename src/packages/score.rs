@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use crate::packages::parser::{token_splitter, FormatConfig};
+
+/// How per-token probabilities are combined into a line score.
+pub enum Aggregate {
+    Mean,
+    Min,
+}
+
+#[derive(Debug)]
+pub struct LineScore {
+    pub line_no: usize,
+    pub raw: String,
+    // log10 probability; lower is rarer
+    pub score: f64,
+}
+
+/// log10 P(w3 | w1 w2) from the 2-gram and 3-gram counts, with add-one smoothing over a
+/// vocabulary of `vocab_size` tokens so that unseen 3-grams get a small non-zero probability.
+pub fn conditional_log_prob(w1: &str, w2: &str, w3: &str, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, vocab_size: usize) -> f64 {
+    let c3 = *triple_dict.get(&format!("{}^{}^{}", w1, w2, w3)).unwrap_or(&0) as f64;
+    let c2 = *double_dict.get(&format!("{}^{}", w1, w2)).unwrap_or(&0) as f64;
+    ((c3 + 1.0) / (c2 + vocab_size as f64)).log10()
+}
+
+/// Scores each line by the probability of each of its tokens given the two before it (the
+/// first tokens are conditioned on the end of the previous line), and returns the lines
+/// rarest first. Lines with nothing to score get 0, i.e. certain.
+pub fn score_lines(lines: &[String], config: &FormatConfig, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, vocab_size: usize, aggregate: &Aggregate) -> Vec<LineScore> {
+    let mut scores = vec![];
+    let mut prev: Vec<String> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let tokens = token_splitter(line.to_string(), config);
+        let extended: Vec<String> = [&prev[prev.len().saturating_sub(2)..], &tokens[..]].concat();
+        let log_probs: Vec<f64> = extended.windows(3)
+            .map(|t| conditional_log_prob(&t[0], &t[1], &t[2], double_dict, triple_dict, vocab_size))
+            .collect();
+        let score = match (log_probs.is_empty(), aggregate) {
+            (true, _) => 0.0,
+            (false, Aggregate::Mean) => log_probs.iter().sum::<f64>() / log_probs.len() as f64,
+            (false, Aggregate::Min) => log_probs.iter().cloned().fold(f64::INFINITY, f64::min),
+        };
+        scores.push(LineScore { line_no: i + 1, raw: line.clone(), score });
+        prev = tokens;
+    }
+    scores.sort_by(|a, b| a.score.total_cmp(&b.score).then(a.line_no.cmp(&b.line_no)));
+    scores
+}
+
+pub fn print_scores(scores: &[LineScore]) {
+    for s in scores {
+        println!("{:.4} line {}: {}", s.score, s.line_no, s.raw);
+    }
+}
+
+#[test]
+fn test_score_lines() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::dictionary_builder;
+
+    let config = FormatConfig::new(&Linux);
+    let (double_dict, triple_dict, all_token_list) = dictionary_builder("data/Linux_2k.log".to_string(), config.clone());
+    let lines = vec![
+        "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string(),
+        "Jun 14 15:16:03 combo kernel: flux capacitor overheated badly".to_string(),
+        "Jun 14 15:16:04 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string(),
+    ];
+    for aggregate in [Aggregate::Mean, Aggregate::Min] {
+        let scores = score_lines(&lines, &config, &double_dict, &triple_dict, all_token_list.len(), &aggregate);
+        assert_eq!(scores[0].line_no, 2);
+        assert!(scores[0].score < scores[1].score);
+    }
+}
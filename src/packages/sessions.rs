@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use regex::Regex;
+
+use crate::packages::csv::row;
+use crate::packages::template::Record;
+
+// Groups records into sessions by an identifier, such as the block id of HDFS lines, and gives
+// each session the sequence of its template ids: the input DeepLog- or LogCluster-style
+// detectors expect. The identifier is read from the raw line, before censoring erases it.
+
+/// Where a record's session identifiers come from.
+pub enum SessionKey {
+    /// every match in the raw line, or of the regexp's first group if it has one; a line that
+    /// mentions several identifiers belongs to each of their sessions
+    Pattern(Regex),
+    /// a header field of the format, e.g. Pid or Node
+    Field(String),
+}
+
+/// Template ids are positions in `templates`, numbered in order of first appearance over all
+/// records, including those in no session.
+pub struct Sessions {
+    pub templates: Vec<String>,
+    /// each session's identifier and template ids, in order of first appearance
+    pub sessions: Vec<(String, Vec<usize>)>,
+}
+
+fn identifiers(record: &Record, key: &SessionKey, re: &Regex) -> Vec<String> {
+    let mut ids: Vec<String> = match key {
+        SessionKey::Pattern(pattern) => pattern.captures_iter(&record.raw)
+            .map(|c| c.get(1).unwrap_or_else(|| c.get(0).unwrap()).as_str().to_string())
+            .collect(),
+        SessionKey::Field(field) => re.captures(record.raw.trim())
+            .and_then(|c| c.name(field).map(|f| f.as_str().to_string()))
+            .into_iter().collect(),
+    };
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(id.clone()));
+    ids
+}
+
+/// Splits `records` into sessions by `key`; `re` is the format regexp, for SessionKey::Field.
+pub fn sessionize(records: &[Record], key: &SessionKey, re: &Regex) -> Sessions {
+    group(records, |r| identifiers(r, key, re))
+}
+
+/// The whole log as a single session called "log", for analyses that follow line order.
+pub fn whole_log(records: &[Record]) -> Sessions {
+    group(records, |_| vec!["log".to_string()])
+}
+
+fn group<F>(records: &[Record], ids: F) -> Sessions
+where F: Fn(&Record) -> Vec<String>, {
+    let mut template_ids: HashMap<String, usize> = HashMap::new();
+    let mut session_ids: HashMap<String, usize> = HashMap::new();
+    let mut result = Sessions { templates: vec![], sessions: vec![] };
+    for record in records.iter().filter(|r| !r.tokens.is_empty()) {
+        let template = record.template();
+        let t = *template_ids.entry(template.clone()).or_insert_with(|| {
+            result.templates.push(template);
+            result.templates.len() - 1
+        });
+        for id in ids(record) {
+            let s = *session_ids.entry(id.clone()).or_insert_with(|| {
+                result.sessions.push((id, vec![]));
+                result.sessions.len() - 1
+            });
+            result.sessions[s].1.push(t);
+        }
+    }
+    result
+}
+
+/// One row per session: its identifier, then its template ids separated by spaces.
+pub fn write_sequences<W: Write>(out: &mut W, sessions: &Sessions) -> io::Result<()> {
+    writeln!(out, "session,templates")?;
+    for (id, sequence) in &sessions.sessions {
+        let sequence: Vec<String> = sequence.iter().map(|t| t.to_string()).collect();
+        writeln!(out, "{}", row(&[id.clone(), sequence.join(" ")]))?;
+    }
+    Ok(())
+}
+
+/// The template behind each id, as id,template rows.
+pub fn write_template_table<W: Write>(out: &mut W, templates: &[String]) -> io::Result<()> {
+    writeln!(out, "id,template")?;
+    for (id, template) in templates.iter().enumerate() {
+        writeln!(out, "{}", row(&[id.to_string(), template.clone()]))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sessionize_hdfs_blocks() {
+    use crate::LogFormat::HDFS;
+    use crate::packages::log_parser::{LogParser, NgramParser};
+    use crate::packages::parser::{format_string, read_log_lines, regex_generator, FormatConfig};
+
+    let lines = read_log_lines("data/HDFS_2k.log").unwrap();
+    let mut parser = NgramParser::new(&FormatConfig::new(&HDFS), 3);
+    parser.train(&lines);
+    let records = parser.parse(&lines);
+    let re = regex_generator(format_string(&HDFS));
+    let sessions = sessionize(&records, &SessionKey::Pattern(Regex::new(r"blk_-?\d+").unwrap()), &re);
+
+    // every line of this sample mentions at least one block
+    let total: usize = sessions.sessions.iter().map(|(_, s)| s.len()).sum();
+    assert!(total >= lines.len());
+    let (id, sequence) = &sessions.sessions[0];
+    assert_eq!(id, "blk_38865049064139660");
+    let mentioning: Vec<&Record> = records.iter().filter(|r| r.raw.contains("blk_38865049064139660 ") || r.raw.ends_with("blk_38865049064139660")).collect();
+    assert_eq!(sequence.len(), mentioning.len());
+    assert_eq!(sessions.templates[sequence[0]], mentioning[0].template());
+
+    let by_pid = sessionize(&records, &SessionKey::Field("Pid".to_string()), &re);
+    assert_eq!(by_pid.sessions.iter().map(|(_, s)| s.len()).sum::<usize>(), records.len());
+
+    let mut out = vec![];
+    write_sequences(&mut out, &sessions).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with(&format!("session,templates\nblk_38865049064139660,{}", sequence[0])));
+}
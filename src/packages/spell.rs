@@ -0,0 +1,147 @@
+use crate::packages::log_parser::LogParser;
+use crate::packages::parser::{token_splitter, token_splitter_with_spans, FormatConfig};
+use crate::packages::template::Record;
+
+// Spell (Du and Li, ICDM 2016): each cluster keeps a template, and a new line joins the cluster
+// whose template shares the longest common subsequence with it, provided that is at least tau
+// times the line's length. The template then becomes that subsequence, with <*> wherever
+// tokens were dropped. Unlike Drain, lines of different lengths can share a template.
+//
+// The paper looks lines up in a prefix tree of templates first and only computes LCSs when
+// that fails. Here every line is compared with every template whose literal tokens could be
+// enough, so a line costs O(templates * tokens^2) and Spell slows down as templates pile up.
+
+const WILDCARD: &str = "<*>";
+
+pub struct SpellParser {
+    config: FormatConfig,
+    tau: f64,
+    pub clusters: Vec<Vec<String>>,
+}
+
+/// Pairs of (template index, token index) of a longest common subsequence; <*> matches nothing.
+fn lcs(template: &[String], tokens: &[String]) -> Vec<(usize, usize)> {
+    let (n, m) = (template.len(), tokens.len());
+    let mut table = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if template[i] == tokens[j] && template[i] != WILDCARD {
+                table[i+1][j+1] + 1
+            } else {
+                table[i+1][j].max(table[i][j+1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut pairs = vec![];
+    while i < n && j < m {
+        if template[i] == tokens[j] && template[i] != WILDCARD {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i+1][j] >= table[i][j+1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// The template's tokens in `pairs`, with a single <*> wherever either side skips tokens.
+fn merge(template: &[String], tokens_len: usize, pairs: &[(usize, usize)]) -> Vec<String> {
+    let mut merged = vec![];
+    let (mut next_i, mut next_j) = (0, 0);
+    for (i, j) in pairs {
+        if *i > next_i || *j > next_j {
+            merged.push(WILDCARD.to_string());
+        }
+        merged.push(template[*i].clone());
+        (next_i, next_j) = (i + 1, j + 1);
+    }
+    if next_i < template.len() || next_j < tokens_len {
+        merged.push(WILDCARD.to_string());
+    }
+    merged
+}
+
+impl SpellParser {
+    /// `tau` is the fraction of a line that must be in common with a template; the paper uses 0.5.
+    pub fn new(config: &FormatConfig, tau: f64) -> SpellParser {
+        SpellParser { config: config.clone(), tau, clusters: vec![] }
+    }
+
+    fn best_cluster(&self, tokens: &[String]) -> Option<(usize, Vec<(usize, usize)>)> {
+        let needed = self.tau * tokens.len() as f64;
+        self.clusters.iter().enumerate()
+            // the LCS can't be longer than the template's literal tokens
+            .filter(|(_, t)| t.iter().filter(|x| x.as_str() != WILDCARD).count() as f64 >= needed)
+            .map(|(c, t)| (c, lcs(t, tokens)))
+            .filter(|(_, pairs)| pairs.len() as f64 >= needed && !pairs.is_empty())
+            // longest LCS, then the shortest template
+            .max_by(|(a, pa), (b, pb)| pa.len().cmp(&pb.len()).then(self.clusters[*b].len().cmp(&self.clusters[*a].len())))
+    }
+
+    /// Adds a line to the best-matching cluster, or starts a new one; returns the cluster.
+    pub fn add(&mut self, tokens: &[String]) -> usize {
+        match self.best_cluster(tokens) {
+            Some((c, pairs)) => {
+                self.clusters[c] = merge(&self.clusters[c], tokens.len(), &pairs);
+                c
+            }
+            None => {
+                self.clusters.push(tokens.to_vec());
+                self.clusters.len() - 1
+            }
+        }
+    }
+}
+
+impl LogParser for SpellParser {
+    fn train(&mut self, lines: &[String]) {
+        for line in lines {
+            let tokens = token_splitter(line.to_string(), &self.config);
+            if !tokens.is_empty() {
+                self.add(&tokens);
+            }
+        }
+    }
+
+    // A line is matched against the trained clusters without joining them. A token is dynamic
+    // if it is not part of the LCS with the best template, so each <*> of the template may
+    // cover several tokens of the line. A line like none seen in training keeps all its tokens.
+    fn parse(&self, lines: &[String]) -> Vec<Record> {
+        lines.iter().enumerate().map(|(i, raw)| {
+            let (tokens, censored, spans) = token_splitter_with_spans(raw.to_string(), &self.config);
+            let dynamic = match self.best_cluster(&tokens) {
+                None => vec![],
+                Some((_, pairs)) => {
+                    let kept: Vec<usize> = pairs.into_iter().map(|(_, j)| j).collect();
+                    (0..tokens.len()).filter(|j| !kept.contains(j)).collect()
+                }
+            };
+            Record { line_no: i + 1, raw: raw.clone(), tokens, dynamic, censored, spans, timestamp: None }
+        }).collect()
+    }
+}
+
+#[test]
+fn test_spell_parser() {
+    use crate::LogFormat::Spark;
+    use crate::packages::log_parser::template_counts;
+    use crate::packages::parser::read_log_lines;
+
+    let lines = read_log_lines("data/from_paper.log").unwrap();
+    let mut spell = SpellParser::new(&FormatConfig::new(&Spark), 0.5);
+    spell.train(&lines);
+    let records = spell.parse(&lines);
+    assert_eq!(spell.clusters.len(), 2);
+    assert_eq!(template_counts(&records), vec![
+        ("Input split: <*>".to_string(), 5),
+        ("Found block <*> locally".to_string(), 4),
+    ]);
+
+    let t = |s: &str| s.split_whitespace().map(|x| x.to_string()).collect::<Vec<String>>();
+    assert_eq!(merge(&t("a x e"), 5, &lcs(&t("a x e"), &t("a b c d e"))), t("a <*> e"));
+    assert_eq!(merge(&t("a <*> e"), 3, &lcs(&t("a <*> e"), &t("a e f"))), t("a <*> e <*>"));
+}
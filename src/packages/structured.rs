@@ -0,0 +1,158 @@
+use regex::Regex;
+
+use crate::packages::parser::uncensored_offset;
+use crate::packages::template::Record;
+
+// A line stored as its template and parameters. Unlike Record::template, the template keeps
+// every byte of the line that is not a parameter (header punctuation, spacing, delimiters the
+// tokenizer dropped), so putting the parameters back gives the line exactly.
+
+/// A line as literal text with parameters in between: the header fields the format regexp
+/// captures, the dynamic tokens, and the censored values inside static tokens. `segments`
+/// has one more entry than `params`, and is the same for lines that differ only in their
+/// parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structured {
+    pub segments: Vec<String>,
+    pub params: Vec<String>,
+}
+
+/// Interleaves `segments` and `params`, giving back the line they were taken from.
+pub fn reconstruct(segments: &[String], params: &[String]) -> String {
+    let mut line = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        line.push_str(segment);
+        if let Some(p) = params.get(i) {
+            line.push_str(p);
+        }
+    }
+    line
+}
+
+/// Byte ranges of the parameters of `record` in its raw line, in order.
+fn param_spans(record: &Record, re: &Regex) -> Vec<(usize, usize)> {
+    let raw = record.raw.as_str();
+    let indent = raw.len() - raw.trim_start().len();
+    let m = match re.captures(raw.trim()) {
+        Some(m) => m,
+        None => return vec![],
+    };
+
+    let mut spans: Vec<(usize, usize)> = re.capture_names().flatten()
+        .filter(|name| *name != "Content")
+        .filter_map(|name| m.name(name))
+        .map(|h| (indent + h.start(), indent + h.end()))
+        .collect();
+    spans.extend(record.dynamic.iter().map(|i| record.spans[*i]));
+    // censored values that are not already inside a dynamic token
+    let content_start = indent + m.name("Content").unwrap().start();
+    for v in &record.censored {
+        let start = content_start + uncensored_offset(v.start, &record.censored) - 1;
+        spans.push((start, start + v.value.len()));
+    }
+
+    // a censored value inside a dynamic token goes with the token
+    spans.sort();
+    let mut disjoint: Vec<(usize, usize)> = vec![];
+    for (start, end) in spans {
+        match disjoint.last_mut() {
+            Some((_, last_end)) if start < *last_end => *last_end = end.max(*last_end),
+            _ => disjoint.push((start, end)),
+        }
+    }
+    disjoint
+}
+
+/// Splits the raw line of `record` into template segments and parameters.
+pub fn structure(record: &Record, re: &Regex) -> Structured {
+    let raw = record.raw.as_str();
+    let mut segments = vec![];
+    let mut params = vec![];
+    let mut last = 0;
+    for (start, end) in param_spans(record, re) {
+        segments.push(raw[last..start].to_string());
+        params.push(raw[start..end].to_string());
+        last = end;
+    }
+    segments.push(raw[last..].to_string());
+    Structured { segments, params }
+}
+
+/// A line of a log file as text. Lines that are not UTF-8 are decoded byte by byte (as
+/// Latin-1) instead of lossily, so that `to_bytes` gives them back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawLine {
+    pub text: String,
+    pub latin1: bool,
+}
+
+impl RawLine {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.latin1 {
+            true => self.text.chars().map(|c| c as u8).collect(),
+            false => self.text.as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Splits a log file's bytes at every \n. Nothing else is dropped: \r stays on its line, and
+/// a file ending in \n has an empty last line, so joining the lines with \n gives the bytes back.
+pub fn raw_lines(bytes: &[u8]) -> Vec<RawLine> {
+    bytes.split(|b| *b == b'\n').map(|line| match std::str::from_utf8(line) {
+        Ok(text) => RawLine { text: text.to_string(), latin1: false },
+        Err(_) => RawLine { text: line.iter().map(|b| *b as char).collect(), latin1: true },
+    }).collect()
+}
+
+#[test]
+fn test_structure() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::{token_splitter_with_spans, FormatConfig};
+
+    let line = "Jun 15 02:04:59 combo sshd(pam_unix)[20884]: authentication failure; rhost=220.135.151.1  user=root at 02:04:59\r".to_string();
+    let config = FormatConfig::split_punctuation(&Linux);
+    let (tokens, censored, spans) = token_splitter_with_spans(line.clone(), &config);
+    let record = Record { line_no: 1, raw: line.clone(), tokens, dynamic: vec![6], censored, spans, timestamp: None };
+    let structured = structure(&record, &config.re);
+    assert_eq!(structured.segments.join("<*>"), "<*> <*> <*> <*> <*>: authentication failure; rhost=<*>  user=root <*> <*>\r");
+    assert_eq!(structured.params, vec!["Jun", "15", "02:04:59", "combo", "sshd(pam_unix)[20884]", "220.135.151.1", "at", "02:04:59"]);
+    assert_eq!(reconstruct(&structured.segments, &structured.params), line);
+}
+
+#[test]
+fn test_params_data_files() {
+    use std::collections::HashMap;
+    use crate::LogFormat;
+    use crate::packages::log_parser::{LogParser, NgramParser};
+    use crate::packages::parser::{format_string, regex_generator, FormatConfig};
+
+    let formats = [("HDFS", LogFormat::HDFS), ("HPC", LogFormat::HPC), ("HealthApp", LogFormat::HealthApp),
+                   ("Linux", LogFormat::Linux), ("Proxifier", LogFormat::Proxifier), ("from_paper", LogFormat::Spark)];
+    for entry in std::fs::read_dir("data").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let lf = &formats.iter().find(|(prefix, _)| name.starts_with(prefix)).unwrap().1;
+        let bytes = std::fs::read(&path).unwrap();
+        let lines = raw_lines(&bytes);
+        let texts: Vec<String> = lines.iter().map(|l| l.text.clone()).collect();
+
+        let mut parser = NgramParser::new(&FormatConfig::split_punctuation(lf), 100);
+        parser.train(&texts);
+        let re = regex_generator(format_string(lf));
+        let records = parser.parse(&texts);
+        // lines share a template when their segments are the same, as in compress
+        let mut templates: HashMap<Vec<String>, usize> = HashMap::new();
+        for (record, line) in records.iter().zip(&lines) {
+            let structured = structure(record, &re);
+            let headers = re.captures(record.raw.trim()).map_or(0, |m| re.capture_names().flatten().filter(|h| *h != "Content" && m.name(h).is_some()).count());
+            // after the header fields come the parameters of the record, in order
+            assert_eq!(structured.params[headers..], record.parameters()[..], "{} line {}", name, record.line_no);
+            let count = templates.len();
+            templates.entry(structured.segments.clone()).or_insert(count);
+            let rebuilt = RawLine { text: reconstruct(&structured.segments, &structured.params), latin1: line.latin1 };
+            assert_eq!(rebuilt, *line, "{} line {}", name, record.line_no);
+        }
+        // lines that differ only in their parameters share a template
+        assert!(templates.len() < records.len(), "{} has a template per line", name);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::packages::json::quote;
+use crate::packages::parser::{token_splitter_with_spans, CensoredValue, FormatConfig, SplitLine};
+
+/// One raw log line, its content tokens, and which of those tokens are dynamic.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub line_no: usize,
+    pub raw: String,
+    pub tokens: Vec<String>,
+    pub dynamic: Vec<usize>,
+    // what censoring replaced in this line, in order
+    pub censored: Vec<CensoredValue>,
+    // byte range of each token in `raw`
+    pub spans: Vec<(usize, usize)>,
+    // milliseconds since 1970 from the header, once a TimestampParser has read it
+    pub timestamp: Option<i64>,
+}
+
+/// A dynamic token: its index among the content tokens, and its byte range in the raw line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicToken {
+    pub index: usize,
+    pub token: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A parameter of a record: the index of the token it is in, what stands for it in the
+/// template (<*> for a dynamic token, the placeholder for a censored value), and its text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub token: usize,
+    pub label: String,
+    pub value: String,
+}
+
+impl Record {
+    /// The content tokens with every dynamic token replaced by <*>.
+    pub fn template(&self) -> String {
+        self.tokens.iter().enumerate()
+            .map(|(i, t)| if self.dynamic.contains(&i) { "<*>" } else { t.as_str() })
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    pub fn dynamic_tokens(&self) -> Vec<DynamicToken> {
+        self.dynamic.iter().map(|i| {
+            let (start, end) = self.spans[*i];
+            DynamicToken { index: *i, token: self.tokens[*i].clone(), start, end }
+        }).collect()
+    }
+
+    /// The original text behind the template: each dynamic token with its censored values put
+    /// back, and each censored value inside a static token, in order of appearance.
+    pub fn parameters(&self) -> Vec<String> {
+        self.labelled_parameters().into_iter().map(|p| p.value).collect()
+    }
+
+    /// Like parameters, with where each one sits in the template.
+    pub fn labelled_parameters(&self) -> Vec<Parameter> {
+        let mut values = self.censored.iter().peekable();
+        let mut params = vec![];
+        for (i, token) in self.tokens.iter().enumerate() {
+            let is_dynamic = self.dynamic.contains(&i);
+            let mut uncensored = String::new();
+            let mut rest = token.as_str();
+            while let Some((at, v)) = values.peek().and_then(|v| rest.find(&v.placeholder).map(|at| (at, v))) {
+                uncensored.push_str(&rest[..at]);
+                uncensored.push_str(&v.value);
+                if !is_dynamic {
+                    params.push(Parameter { token: i, label: v.placeholder.clone(), value: v.value.clone() });
+                }
+                rest = &rest[at + v.placeholder.len()..];
+                values.next();
+            }
+            if is_dynamic {
+                uncensored.push_str(rest);
+                params.push(Parameter { token: i, label: "<*>".to_string(), value: uncensored });
+            }
+        }
+        params
+    }
+}
+
+#[test]
+fn test_derive_2grams_from_trigram() {
+    let twograms_oracle = vec![("one^two"), 
+                               ("two^three")];
+    let twograms = derive_2grams_from_trigram("one^two^three");
+    assert_eq!(twograms_oracle, twograms);
+}
+
+fn derive_2grams_from_trigram(trigram:&str) -> Vec<String> {
+    let grams : Vec<&str> = trigram.split("^").collect();
+    return vec![format!("{}^{}", grams[0], grams[1]), 
+                format!("{}^{}", grams[1], grams[2])];
+}
+
+/// The 2-grams of the 3-grams of `tokens` that occur less often than cutoff in the corpus,
+/// with their own counts, in order of first appearance. These are the 2-grams the classifier
+/// looks at; it keeps those that are below the cutoff themselves.
+pub fn candidate_2grams(tokens: &[String], double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, cutoff: i32) -> Vec<(String, i32)> {
+    let mut seen = HashSet::new();
+    let mut candidates = vec![];
+    for triple in tokens.windows(3) {
+        let three_gram = format!("{}^{}^{}", triple[0], triple[1], triple[2]);
+        if triple_dict.get(&three_gram).is_some_and(|c| *c < cutoff) {
+            for two_gram in derive_2grams_from_trigram(&three_gram) {
+                if seen.insert(two_gram.clone()) {
+                    // a windowed dictionary may already have dropped a 2-gram that belongs to an older line
+                    let count = *double_dict.get(&two_gram).unwrap_or(&0);
+                    candidates.push((two_gram, count));
+                }
+            }
+        }
+    }
+    candidates
+}
+
+/// The logram classifier: a token is dynamic if both 2-grams around it come from uncommon
+/// 3-grams and are themselves uncommon. Returns indices into `tokens`.
+pub fn dynamic_token_indices(tokens: &[String], double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, cutoff: i32) -> Vec<usize> {
+    let uncommon_2grams: HashSet<String> = candidate_2grams(tokens, double_dict, triple_dict, cutoff).into_iter()
+        .filter(|(_, count)| *count < cutoff)
+        .map(|(two_gram, _)| two_gram)
+        .collect();
+
+    // now, look for tokens with uncommon 2grams on both sides
+    let mut dynamic = vec![];
+    for (i, triple) in tokens.windows(3).enumerate() {
+        let two_gram1 = format!("{}^{}", triple[0], triple[1]);
+        let two_gram2 = format!("{}^{}", triple[1], triple[2]);
+        if uncommon_2grams.contains(&two_gram1) && uncommon_2grams.contains(&two_gram2) {
+            dynamic.push(i + 1);
+        }
+    }
+    dynamic
+}
+
+/// The dynamic tokens as a JSON array of {"index", "token", "start", "end", "text"}, where
+/// text is what the token covers in `raw`.
+pub fn dynamic_tokens_json(dynamic_tokens: &[DynamicToken], raw: &str) -> String {
+    let objects: Vec<String> = dynamic_tokens.iter().map(|d| format!(
+        "{{\"index\": {}, \"token\": {}, \"start\": {}, \"end\": {}, \"text\": {}}}",
+        d.index, quote(&d.token), d.start, d.end, quote(&raw[d.start..d.end])
+    )).collect();
+    format!("[{}]", objects.join(", "))
+}
+
+/// Splits every line and classifies its tokens, using the last two tokens of the previous
+/// line and the first two of the next as context, as the dictionary builder does.
+pub fn extract_records(lines: &[String], config: &FormatConfig, double_dict: &HashMap<String, i32>, triple_dict: &HashMap<String, i32>, cutoff: i32) -> Vec<Record> {
+    let split: Vec<SplitLine> = lines.iter().map(|l| token_splitter_with_spans(l.to_string(), config)).collect();
+    let tokenized: Vec<&Vec<String>> = split.iter().map(|s| &s.0).collect();
+    let mut records = vec![];
+    for (i, (tokens, censored, spans)) in split.iter().enumerate() {
+        let before = match i {
+            0 => &[][..],
+            _ => { let p = &tokenized[i-1]; &p[p.len().saturating_sub(2)..] }
+        };
+        let after = match tokenized.get(i+1) {
+            None => &[][..],
+            Some(n) => &n[..n.len().min(2)],
+        };
+        let extended: Vec<String> = [before, tokens, after].concat();
+        let dynamic = dynamic_token_indices(&extended, double_dict, triple_dict, cutoff)
+            .into_iter()
+            .filter(|d| *d >= before.len() && *d < before.len() + tokens.len())
+            .map(|d| d - before.len())
+            .collect();
+        records.push(Record { line_no: i + 1, raw: lines[i].clone(), tokens: tokens.clone(), dynamic, censored: censored.clone(), spans: spans.clone(), timestamp: None });
+    }
+    records
+}
+
+#[test]
+fn test_extract_records() {
+    use crate::LogFormat::Spark;
+    use crate::packages::parser::parse_raw;
+
+    let (double_dict, triple_dict, _) = parse_raw("data/from_paper.log".to_string(), &FormatConfig::new(&Spark), false, 1);
+    let lines: Vec<String> = std::fs::read_to_string("data/from_paper.log").unwrap().lines().map(|l| l.to_string()).collect();
+    let records = extract_records(&lines, &FormatConfig::new(&Spark), &double_dict, &triple_dict, 3);
+    assert_eq!(records.len(), lines.len());
+    assert_eq!(records[1].template(), "Input split: <*>");
+    assert_eq!(records[1].dynamic, vec![2]);
+    assert_eq!(records[1].parameters(), vec!["hdfs://hostname/2kSOSP.log:14584+7292"]);
+}
+
+#[test]
+fn test_record_parameters() {
+    use crate::LogFormat::Linux;
+
+    let line = "Jun 15 02:04:59 combo sshd(pam_unix)[20884]: authentication failure; rhost=220.135.151.1 user=root at 02:04:59".to_string();
+    let (tokens, censored, spans) = token_splitter_with_spans(line.clone(), &FormatConfig::split_punctuation(&Linux));
+    let record = Record { line_no: 1, raw: line, tokens, dynamic: vec![6], censored, spans, timestamp: None };
+    assert_eq!(record.template(), "authentication failure rhost= <IP> user= root <*> <TIME>");
+    assert_eq!(record.parameters(), vec!["220.135.151.1", "at", "02:04:59"]);
+
+    let dynamic = record.dynamic_tokens();
+    assert_eq!(dynamic, vec![DynamicToken { index: 6, token: "at".to_string(), start: 99, end: 101 }]);
+    assert_eq!(&record.raw[99..101], "at");
+    assert_eq!(dynamic_tokens_json(&dynamic, &record.raw), r#"[{"index": 6, "token": "at", "start": 99, "end": 101, "text": "at"}]"#);
+}
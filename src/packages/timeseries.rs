@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use crate::packages::csv::row;
+use crate::packages::template::Record;
+use crate::packages::timestamp::format_timestamp;
+
+// How often each template occurs per fixed time bucket. Buckets are aligned to multiples of
+// their length since 1970, so series of different logs line up.
+
+pub struct TimeSeries {
+    /// bucket length in milliseconds
+    pub bucket: i64,
+    /// templates in order of first appearance
+    pub templates: Vec<String>,
+    /// count of each template in each bucket that has any line, by bucket start
+    pub counts: BTreeMap<i64, Vec<usize>>,
+}
+
+impl TimeSeries {
+    /// Every bucket start from the first bucket with a line to the last, empty ones included.
+    pub fn buckets(&self) -> Vec<i64> {
+        match (self.counts.keys().next(), self.counts.keys().next_back()) {
+            (Some(first), Some(last)) => (0..=(last - first) / self.bucket).map(|i| first + i * self.bucket).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The count of template `t` in the bucket starting at `start`.
+    pub fn count(&self, start: i64, t: usize) -> usize {
+        self.counts.get(&start).and_then(|c| c.get(t)).copied().unwrap_or(0)
+    }
+}
+
+/// Counts the templates of `records` per bucket of `bucket` milliseconds. Records need their
+/// timestamps attached; one without takes the previous record's, and records before the
+/// first timestamp are left out. Template ids still count those records, so that they are the
+/// ids `sessions` gives the same records.
+pub fn time_series(records: &[Record], bucket: i64) -> TimeSeries {
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut series = TimeSeries { bucket, templates: vec![], counts: BTreeMap::new() };
+    let mut last = None;
+    for record in records.iter().filter(|r| !r.tokens.is_empty()) {
+        let template = record.template();
+        let id = *ids.entry(template.clone()).or_insert_with(|| {
+            series.templates.push(template);
+            series.templates.len() - 1
+        });
+        last = record.timestamp.or(last);
+        let t = match last {
+            Some(t) => t,
+            None => continue,
+        };
+        let counts = series.counts.entry(t.div_euclid(bucket) * bucket).or_default();
+        if counts.len() <= id {
+            counts.resize(id + 1, 0);
+        }
+        counts[id] += 1;
+    }
+    series
+}
+
+/// One row per bucket and one column per template.
+pub fn write_wide<W: Write>(out: &mut W, series: &TimeSeries) -> io::Result<()> {
+    let header: Vec<&str> = std::iter::once("time").chain(series.templates.iter().map(|t| t.as_str())).collect();
+    writeln!(out, "{}", row(&header))?;
+    for start in series.buckets() {
+        let mut fields = vec![format_timestamp(start)];
+        fields.extend((0..series.templates.len()).map(|t| series.count(start, t).to_string()));
+        writeln!(out, "{}", row(&fields))?;
+    }
+    Ok(())
+}
+
+/// One row per bucket and template that occurs in it: time,template,count.
+pub fn write_long<W: Write>(out: &mut W, series: &TimeSeries) -> io::Result<()> {
+    writeln!(out, "time,template,count")?;
+    for (start, counts) in &series.counts {
+        for (t, count) in counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            writeln!(out, "{}", row(&[format_timestamp(*start), series.templates[t].clone(), count.to_string()]))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_time_series() {
+    let record = |template: &str, timestamp: Option<i64>| Record {
+        line_no: 0, raw: String::new(), tokens: template.split(' ').map(|t| t.to_string()).collect(),
+        dynamic: vec![], censored: vec![], spans: vec![], timestamp,
+    };
+    let records = vec![record("a", None), record("a", Some(1_000)), record("b, c", Some(59_999)), record("a", None), record("a", Some(185_000))];
+    let series = time_series(&records, 60_000);
+    assert_eq!(series.templates, vec!["a", "b, c"]);
+    assert_eq!(series.buckets(), vec![0, 60_000, 120_000, 180_000]);
+
+    let mut wide = vec![];
+    write_wide(&mut wide, &series).unwrap();
+    assert_eq!(String::from_utf8(wide).unwrap(), "time,a,\"b, c\"\n\
+        1970-01-01T00:00:00.000,2,1\n1970-01-01T00:01:00.000,0,0\n1970-01-01T00:02:00.000,0,0\n1970-01-01T00:03:00.000,1,0\n");
+    let mut long = vec![];
+    write_long(&mut long, &series).unwrap();
+    assert_eq!(String::from_utf8(long).unwrap(), "time,template,count\n\
+        1970-01-01T00:00:00.000,a,2\n1970-01-01T00:00:00.000,\"b, c\",1\n1970-01-01T00:03:00.000,a,1\n");
+
+    // an untimed template keeps its id
+    let records = vec![record("z", None), record("a", Some(1_000))];
+    assert_eq!(time_series(&records, 60_000).templates, crate::packages::sessions::whole_log(&records).templates);
+}
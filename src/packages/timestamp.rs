@@ -0,0 +1,302 @@
+use std::sync::OnceLock;
+use regex::Regex;
+
+use crate::LogFormat;
+use crate::LogFormat::*;
+use crate::packages::parser::{format_string, regex_generator};
+use crate::packages::template::Record;
+
+// Timestamps are read from the header fields the format regexp captures and normalized to
+// milliseconds since 1970-01-01 00:00. Logs carry no time zone, so times are taken as UTC; what
+// matters is that timestamps from one log compare and subtract correctly.
+
+const SECOND: i64 = 1000;
+const DAY: i64 = 24 * 3600 * SECOND;
+
+/// The header fields that hold the time in `lf`, joined with spaces before matching `shape`.
+fn time_fields(lf: &LogFormat) -> &'static [&'static str] {
+    match lf {
+        Linux => &["Month", "Date", "Time"],
+        HPC | Proxifier | HealthApp => &["Time"],
+        OpenStack | Spark | HDFS | Android => &["Date", "Time"],
+    }
+}
+
+/// How the time fields of `lf` look, with groups y, mo (or mon for a month name), d, h, mi, s,
+/// and ms for milliseconds or frac for a decimal fraction of a second; a missing group is taken
+/// as 0, or as the configured year for y. None for HPC, whose time is in seconds since 1970.
+fn shape(lf: &LogFormat) -> Option<&'static str> {
+    Some(match lf {
+        // Jun 14 15:16:02
+        Linux => r"^(?P<mon>[A-Z][a-z]{2}) (?P<d>\d{1,2}) (?P<h>\d{2}):(?P<mi>\d{2}):(?P<s>\d{2})$",
+        // 2017-05-16 00:00:00.008
+        OpenStack => r"^(?P<y>\d{4})-(?P<mo>\d{2})-(?P<d>\d{2}) (?P<h>\d{2}):(?P<mi>\d{2}):(?P<s>\d{2})(\.(?P<frac>\d{1,3})\d*)?$",
+        // 17/06/09 20:11:11
+        Spark => r"^(?P<y>\d{2})/(?P<mo>\d{2})/(?P<d>\d{2}) (?P<h>\d{2}):(?P<mi>\d{2}):(?P<s>\d{2})$",
+        // 081109 203615
+        HDFS => r"^(?P<y>\d{2})(?P<mo>\d{2})(?P<d>\d{2}) (?P<h>\d{2})(?P<mi>\d{2})(?P<s>\d{2})$",
+        HPC => return None,
+        // [10.30 16:49:06, the format regexp leaves the opening bracket in
+        Proxifier => r"^\[?(?P<mo>\d{2})\.(?P<d>\d{2}) (?P<h>\d{2}):(?P<mi>\d{2}):(?P<s>\d{2})$",
+        // 03-17 16:13:38.811
+        Android => r"^(?P<mo>\d{2})-(?P<d>\d{2}) (?P<h>\d{2}):(?P<mi>\d{2}):(?P<s>\d{2})\.(?P<frac>\d{1,3})$",
+        // 20171223-22:15:29:606, with no zero padding: 22:16:0:11 is 11 milliseconds past 22:16:00
+        HealthApp => r"^(?P<y>\d{4})(?P<mo>\d{2})(?P<d>\d{2})-(?P<h>\d{1,2}):(?P<mi>\d{1,2}):(?P<s>\d{1,2}):(?P<ms>\d{1,3})$",
+    })
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date of `days` after 1970-01-01, as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, m, d)
+}
+
+/// A timestamp as ISO 8601, e.g. 2005-06-14T15:16:02.000
+pub fn format_timestamp(ms: i64) -> String {
+    let (y, m, d) = civil_from_days(ms.div_euclid(DAY));
+    let t = ms.rem_euclid(DAY);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}", y, m, d, t / 3600000, t / 60000 % 60, t / 1000 % 60, t % 1000)
+}
+
+/// This year, the default for timestamps that leave the year out.
+pub fn current_year() -> i64 {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64);
+    civil_from_days(now.div_euclid(DAY)).0
+}
+
+/// Reads the timestamps of one log format.
+pub struct TimestampParser {
+    re: Regex,
+    fields: &'static [&'static str],
+    shape: Option<Regex>,
+    /// the year of timestamps that leave it out (Linux, Proxifier, Android)
+    pub year: i64,
+}
+
+impl TimestampParser {
+    pub fn new(lf: &LogFormat, year: i64) -> TimestampParser {
+        TimestampParser { re: regex_generator(format_string(lf)), fields: time_fields(lf), shape: shape(lf).map(|s| Regex::new(s).unwrap()), year }
+    }
+
+    /// Whether timestamps carry their year; if not, `attach` works it out from `year`.
+    fn has_year(&self) -> bool {
+        self.shape.as_ref().is_none_or(|s| s.capture_names().flatten().any(|n| n == "y"))
+    }
+
+    /// The time in the header of `line`, if it has one.
+    pub fn parse(&self, line: &str) -> Option<i64> {
+        self.parse_in(line, self.year)
+    }
+
+    fn parse_in(&self, line: &str, default_year: i64) -> Option<i64> {
+        let m = self.re.captures(line.trim())?;
+        let fields = self.fields.iter().map(|f| m.name(f).map(|f| f.as_str().trim())).collect::<Option<Vec<_>>>()?;
+        let time = fields.join(" ");
+        let shape = match &self.shape {
+            None => return time.parse::<i64>().ok().map(|s| s * SECOND),
+            Some(shape) => shape,
+        };
+        let c = shape.captures(&time)?;
+        let n = |name: &str| c.name(name).map_or(Some(0), |v| v.as_str().parse::<i64>().ok());
+        let year = match c.name("y") {
+            Some(y) if y.as_str().len() == 2 => 2000 + n("y")?,
+            Some(_) => n("y")?,
+            None => default_year,
+        };
+        let month = match c.name("mon") {
+            Some(name) => MONTHS.iter().position(|m| *m == name.as_str())? as i64 + 1,
+            None => n("mo")?,
+        };
+        let (d, h, mi, s) = (n("d")?, n("h")?, n("mi")?, n("s")?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&d) || h > 23 || mi > 59 || s > 60 {
+            return None;
+        }
+        let ms = match c.name("frac") {
+            // .8 is 800 milliseconds
+            Some(v) => n("frac")? * 10_i64.pow(3 - v.as_str().len() as u32),
+            None => n("ms")?,
+        };
+        Some(days_from_civil(year, month, d) * DAY + ((h * 60 + mi) * 60 + s) * SECOND + ms)
+    }
+
+    /// The timestamp of each line. For timestamps without a year, a jump back of more than half
+    /// a year is taken as the log running into the next year.
+    pub fn timestamps(&self, lines: &[&str]) -> Vec<Option<i64>> {
+        let mut stream = self.stream();
+        lines.iter().map(|line| stream.read(line)).collect()
+    }
+
+    /// Reads timestamps one line at a time, as `timestamps` does for a whole log.
+    pub fn stream(&self) -> TimestampStream<'_> {
+        TimestampStream { parser: self, year: self.year, last: None }
+    }
+
+    /// Sets the timestamp of every record, as `timestamps` reads them.
+    pub fn attach(&self, records: &mut [Record]) {
+        let timestamps = self.timestamps(&records.iter().map(|r| r.raw.as_str()).collect::<Vec<_>>());
+        for (record, t) in records.iter_mut().zip(timestamps) {
+            record.timestamp = t;
+        }
+    }
+
+    /// The numbers (from 1) of the lines from `since` (inclusive) to `until` (exclusive), so that
+    /// consecutive windows do not overlap. `untimed` decides about lines without a timestamp.
+    pub fn in_window(&self, lines: &[String], since: Option<i64>, until: Option<i64>, untimed: &Untimed) -> Vec<usize> {
+        let inside = |t: i64| since.is_none_or(|s| t >= s) && until.is_none_or(|u| t < u);
+        let refs: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        let mut last = None;
+        let mut kept = vec![];
+        for (i, t) in self.timestamps(&refs).into_iter().enumerate() {
+            let keep = match (t, untimed) {
+                (Some(t), _) => inside(t),
+                (None, Untimed::Inherit) => last.is_some_and(inside),
+                (None, Untimed::Include) => true,
+                (None, Untimed::Exclude) => false,
+            };
+            last = t.or(last);
+            if keep {
+                kept.push(i + 1);
+            }
+        }
+        kept
+    }
+}
+
+/// The timestamps of a log read line by line; see `TimestampParser::stream`.
+pub struct TimestampStream<'a> {
+    parser: &'a TimestampParser,
+    year: i64,
+    last: Option<i64>,
+}
+
+impl TimestampStream<'_> {
+    /// The timestamp of the next line of the log.
+    pub fn read(&mut self, line: &str) -> Option<i64> {
+        let mut t = self.parser.parse_in(line, self.year);
+        if let (Some(this), Some(l), false) = (t, self.last, self.parser.has_year()) {
+            if this + 183 * DAY < l {
+                self.year += 1;
+                t = self.parser.parse_in(line, self.year);
+            }
+        }
+        self.last = t.or(self.last);
+        t
+    }
+}
+
+/// What to do with lines that have no timestamp when filtering by time.
+#[derive(Clone, Debug, clap::ValueEnum)]
+pub enum Untimed {
+    /// take the timestamp of the last line that had one, so that continuation lines such as
+    /// stack traces go with the line they continue; lines before any timestamp are left out
+    Inherit,
+    Include,
+    Exclude,
+}
+
+/// Reads a time given on the command line: 2005-06-23, 2005-06-23T23:00, 2005-06-23 23:00:05
+/// or 2005-06-23T23:00:05.250
+pub fn parse_time(s: &str) -> Option<i64> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"^(?P<y>\d{4})-(?P<mo>\d{2})-(?P<d>\d{2})([T ](?P<h>\d{2}):(?P<mi>\d{2})(:(?P<s>\d{2})(\.(?P<frac>\d{1,3}))?)?)?$").unwrap());
+    let c = re.captures(s.trim())?;
+    let n = |name: &str| c.name(name).map_or(0, |v| v.as_str().parse::<i64>().unwrap());
+    let (month, d, h, mi, sec) = (n("mo"), n("d"), n("h"), n("mi"), n("s"));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&d) || h > 23 || mi > 59 || sec > 60 {
+        return None;
+    }
+    let ms = c.name("frac").map_or(0, |v| n("frac") * 10_i64.pow(3 - v.as_str().len() as u32));
+    Some(days_from_civil(n("y"), month, d) * DAY + ((h * 60 + mi) * 60 + sec) * SECOND + ms)
+}
+
+/// Reads a duration given on the command line, e.g. 250ms, 30s, 5m, 1h or 1d, in milliseconds.
+pub fn parse_duration(s: &str) -> Option<i64> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let n: i64 = s[..split].parse().ok()?;
+    let unit = match &s[split..] {
+        "ms" => 1,
+        "s" => SECOND,
+        "m" => 60 * SECOND,
+        "h" => 3600 * SECOND,
+        "d" => DAY,
+        _ => return None,
+    };
+    n.checked_mul(unit).filter(|d| *d > 0)
+}
+
+#[test]
+fn test_parse_timestamps() {
+    let cases = [
+        (Linux, "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown", "2005-06-14T15:16:02.000"),
+        (Linux, "Jun  9 06:06:20 combo syslogd 1.4.1: restart.", "2005-06-09T06:06:20.000"),
+        (HDFS, "081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 for block blk_38865049064139660 terminating", "2008-11-09T20:36:15.000"),
+        (HPC, "134681 node-246 unix.hw state_change.unavailable 1077804742 1 Component State Change: Component is in the unavailable state (HWID=1973)", "2004-02-26T14:12:22.000"),
+        (HealthApp, "20171223-22:15:29:606|Step_LSC|30002312|onStandStepChanged 3579", "2017-12-23T22:15:29.606"),
+        (HealthApp, "20171223-22:16:0:11|Step_LSC|30002312|onStandStepChanged 3579", "2017-12-23T22:16:00.011"),
+        (Proxifier, "[10.30 16:49:06] chrome.exe - proxy.cse.cuhk.edu.hk:5070 close, 0 bytes sent, 0 bytes received, lifetime <1 sec", "2005-10-30T16:49:06.000"),
+        (Spark, "17/06/09 20:11:11 INFO rdd.HadoopRDD: Input split: hdfs://hostname/2kSOSP.log:21876+7292", "2017-06-09T20:11:11.000"),
+        (Android, "03-17 16:13:38.811  1702  2395 D WindowManager: printFreezingDisplayLogsopening app wtoken", "2005-03-17T16:13:38.811"),
+    ];
+    for (lf, line, expected) in cases {
+        let parser = TimestampParser::new(&lf, 2005);
+        assert_eq!(parser.parse(line).map(format_timestamp).as_deref(), Some(expected), "{}", line);
+    }
+    assert_eq!(TimestampParser::new(&Linux, 2005).parse("Jun 14 25:16:02 combo sshd: x"), None);
+    assert_eq!(TimestampParser::new(&Linux, 2005).parse("no header"), None);
+}
+
+#[test]
+fn test_attach_rolls_over_years() {
+    let record = |raw: &str| Record { line_no: 0, raw: raw.to_string(), tokens: vec![], dynamic: vec![], censored: vec![], spans: vec![], timestamp: None };
+    let mut records = vec![record("Dec 31 23:59:59 combo kernel: a"), record("garbled"), record("Jan  1 00:00:01 combo kernel: b")];
+    TimestampParser::new(&Linux, 2005).attach(&mut records);
+    let times: Vec<_> = records.iter().map(|r| r.timestamp.map(format_timestamp)).collect();
+    assert_eq!(times, vec![Some("2005-12-31T23:59:59.000".to_string()), None, Some("2006-01-01T00:00:01.000".to_string())]);
+}
+
+#[test]
+fn test_every_data_line_has_a_timestamp() {
+    for (name, lf) in [("HDFS_2k", HDFS), ("HPC_2k", HPC), ("HealthApp_2k", HealthApp), ("Linux_2k", Linux), ("Proxifier_2k", Proxifier), ("from_paper", Spark)] {
+        let parser = TimestampParser::new(&lf, 2005);
+        let text = std::fs::read_to_string(format!("data/{}.log", name)).unwrap();
+        for line in text.lines() {
+            assert!(parser.parse(line).is_some(), "{}: {}", name, line);
+        }
+    }
+}
+
+#[test]
+fn test_in_window() {
+    let lines: Vec<String> = ["Jun 14 15:16:01 combo sshd: a", "  continued", "Jun 14 15:17:00 combo sshd: b", "  continued", "Jun 14 15:18:00 combo sshd: c"]
+        .iter().map(|l| l.to_string()).collect();
+    let parser = TimestampParser::new(&Linux, 2005);
+    let (since, until) = (parse_time("2005-06-14T15:17"), parse_time("2005-06-14 15:18:00"));
+    assert_eq!(parser.in_window(&lines, since, until, &Untimed::Inherit), vec![3, 4]);
+    assert_eq!(parser.in_window(&lines, since, until, &Untimed::Exclude), vec![3]);
+    assert_eq!(parser.in_window(&lines, since, None, &Untimed::Include), vec![2, 3, 4, 5]);
+    assert_eq!(parse_time("2005-06-14").map(format_timestamp).as_deref(), Some("2005-06-14T00:00:00.000"));
+    assert_eq!(parse_time("2005-06-14 15:17:00.25").map(format_timestamp).as_deref(), Some("2005-06-14T15:17:00.250"));
+    assert_eq!(parse_time("14/06/2005"), None);
+    assert_eq!([parse_duration("250ms"), parse_duration("5m"), parse_duration("1d")], [Some(250), Some(300_000), Some(DAY)]);
+    assert_eq!([parse_duration("0s"), parse_duration("5"), parse_duration("m")], [None, None, None]);
+    assert_eq!(parse_duration("9223372036854775807d"), None);
+}
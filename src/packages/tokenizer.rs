@@ -0,0 +1,87 @@
+// How a censored content line is cut into tokens. Splitting on whitespace alone leaves
+// punctuation stuck to words, so `pass;` and `pass` or `rhost=10.0.0.1` and `rhost=<IP>` count
+// as different tokens. Tokens are returned as byte spans of the line, so whatever the tokenizer
+// drops (delimiters, brackets, whitespace) is still in the line between them.
+
+#[derive(Debug, Clone, Default)]
+pub struct Tokenizer {
+    /// characters that separate tokens besides whitespace, e.g. ";,"
+    pub delimiters: String,
+    /// split `key=value` into `key=` and `value`, so that the key can stay static while the value varies
+    pub key_value: bool,
+    /// drop ( [ { before a token and unmatched ) ] } after it
+    pub strip_brackets: bool,
+}
+
+const OPENING: &str = "([{";
+const CLOSING: &str = ")]}";
+
+impl Tokenizer {
+    /// Splits on whitespace only, as `token_splitter` always did.
+    pub fn whitespace() -> Tokenizer {
+        Tokenizer::default()
+    }
+
+    /// Byte ranges of the tokens of `line`, in order.
+    pub fn spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut pieces = vec![];
+        let mut start = None;
+        for (i, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+            let separator = c.is_whitespace() || self.delimiters.contains(c);
+            match (separator, start) {
+                (false, None) => start = Some(i),
+                (true, Some(st)) => {
+                    pieces.push((st, i));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        let mut spans = vec![];
+        for (mut st, mut end) in pieces {
+            if self.strip_brackets {
+                while let Some(c) = line[st..end].chars().next().filter(|c| OPENING.contains(*c)) {
+                    st += c.len_utf8();
+                }
+                // a closing bracket stays if it closes something inside the token, as in node-[0-7]
+                while let Some(c) = line[st..end].chars().next_back().filter(|c| CLOSING.contains(*c)) {
+                    let opening = OPENING.chars().nth(CLOSING.find(c).unwrap()).unwrap();
+                    if line[st..end].contains(opening) {
+                        break;
+                    }
+                    end -= c.len_utf8();
+                }
+            }
+            match line[st..end].find('=') {
+                Some(k) if self.key_value && k > 0 => {
+                    spans.push((st, st + k + 1));
+                    if st + k + 1 < end {
+                        spans.push((st + k + 1, end));
+                    }
+                }
+                _ if st < end => spans.push((st, end)),
+                _ => {}
+            }
+        }
+        spans
+    }
+
+    pub fn split(&self, line: &str) -> Vec<String> {
+        self.spans(line).into_iter().map(|(st, end)| line[st..end].to_string()).collect()
+    }
+}
+
+#[test]
+fn test_tokenizer() {
+    let line = " check pass; user unknown (HWID=1973) rhost=<IP>  logname= [x] nodes:node-[0-7]";
+    assert_eq!(Tokenizer::whitespace().split(line), vec!["check", "pass;", "user", "unknown", "(HWID=1973)", "rhost=<IP>", "logname=", "[x]", "nodes:node-[0-7]"]);
+
+    let tokenizer = Tokenizer { delimiters: ";,".to_string(), key_value: true, strip_brackets: true };
+    let tokens = tokenizer.split(line);
+    assert_eq!(tokens, vec!["check", "pass", "user", "unknown", "HWID=", "1973", "rhost=", "<IP>", "logname=", "x", "nodes:node-[0-7]"]);
+    // the spans point into the line, so what was dropped between tokens is still there
+    let spans = tokenizer.spans(line);
+    assert_eq!(&line[spans[4].1..spans[5].0], "");
+    assert_eq!(&line[spans[5].1..spans[6].0], ") ");
+}
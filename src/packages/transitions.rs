@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::packages::sessions::Sessions;
+
+// Which templates follow which: an edge from A to B counts how often a line of template A is
+// immediately followed by one of template B in the same session (or, for sessions::whole_log,
+// in the log).
+
+pub struct TransitionGraph {
+    pub templates: Vec<String>,
+    /// (from, to) template ids and how often the transition occurs
+    pub edges: BTreeMap<(usize, usize), usize>,
+}
+
+pub fn transitions(sessions: &Sessions) -> TransitionGraph {
+    let mut edges = BTreeMap::new();
+    for (_, sequence) in &sessions.sessions {
+        for pair in sequence.windows(2) {
+            *edges.entry((pair[0], pair[1])).or_insert(0) += 1;
+        }
+    }
+    TransitionGraph { templates: sessions.templates.clone(), edges }
+}
+
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The graph in Graphviz DOT, leaving out edges seen fewer than `min_count` times and the
+/// templates that are left without edges. Edges are labelled and weighted by their count.
+pub fn write_dot<W: Write>(out: &mut W, graph: &TransitionGraph, min_count: usize) -> io::Result<()> {
+    let edges: Vec<(&(usize, usize), &usize)> = graph.edges.iter().filter(|(_, c)| **c >= min_count).collect();
+    let mut nodes: Vec<usize> = edges.iter().flat_map(|((a, b), _)| [*a, *b]).collect();
+    nodes.sort();
+    nodes.dedup();
+
+    writeln!(out, "digraph transitions {{")?;
+    writeln!(out, "    node [shape=box];")?;
+    for t in nodes {
+        writeln!(out, "    t{} [label={}];", t, dot_string(&format!("{}: {}", t, graph.templates[t])))?;
+    }
+    for ((a, b), count) in edges {
+        writeln!(out, "    t{} -> t{} [label=\"{}\", weight={}];", a, b, count, count)?;
+    }
+    writeln!(out, "}}")
+}
+
+#[test]
+fn test_transitions() {
+    let sessions = Sessions {
+        templates: vec!["open \"x\"".to_string(), "read".to_string(), "close".to_string()],
+        sessions: vec![("s1".to_string(), vec![0, 1, 1, 2]), ("s2".to_string(), vec![0, 1, 2])],
+    };
+    let graph = transitions(&sessions);
+    assert_eq!(graph.edges.iter().map(|(e, c)| (*e, *c)).collect::<Vec<_>>(), vec![((0, 1), 2), ((1, 1), 1), ((1, 2), 2)]);
+
+    let mut dot = vec![];
+    write_dot(&mut dot, &graph, 2).unwrap();
+    assert_eq!(String::from_utf8(dot).unwrap(), "digraph transitions {\n    node [shape=box];\n\
+        \x20   t0 [label=\"0: open \\\"x\\\"\"];\n    t1 [label=\"1: read\"];\n    t2 [label=\"2: close\"];\n\
+        \x20   t0 -> t1 [label=\"2\", weight=2];\n    t1 -> t2 [label=\"2\", weight=2];\n}\n");
+}
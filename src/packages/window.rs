@@ -0,0 +1,329 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use crate::packages::dictionary::Dictionaries;
+use crate::packages::parser::{token_splitter, FormatConfig};
+use crate::packages::timestamp::TimestampParser;
+
+// Each line contributes the same n-grams as in process_dictionary_builder_line: its own tokens
+// with the last two tokens of the previous line in front and the first two of the next line
+// behind. Since we only know the next line once it arrives, lines are held back by one.
+struct LineGrams {
+    pending: Option<(Vec<String>, i64)>,
+    prev1: Option<String>,
+    prev2: Option<String>,
+}
+
+impl LineGrams {
+    fn new() -> LineGrams {
+        LineGrams { pending: None, prev1: None, prev2: None }
+    }
+
+    /// Takes the tokens of the next line; returns the n-grams of the line before it, if any.
+    fn push(&mut self, tokens: Vec<String>, at: i64) -> Option<(i64, Vec<String>, Vec<String>)> {
+        let next = (tokens.first().cloned(), tokens.get(1).cloned());
+        let done = self.pending.take().map(|(t, a)| self.grams(t, a, next));
+        self.pending = Some((tokens, at));
+        done
+    }
+
+    fn finish(&mut self) -> Option<(i64, Vec<String>, Vec<String>)> {
+        self.pending.take().map(|(t, a)| self.grams(t, a, (None, None)))
+    }
+
+    fn grams(&mut self, tokens: Vec<String>, at: i64, (next1, next2): (Option<String>, Option<String>)) -> (i64, Vec<String>, Vec<String>) {
+        if tokens.is_empty() {
+            (self.prev1, self.prev2) = (None, None);
+            return (at, vec![], vec![]);
+        }
+        let last1 = tokens.last().cloned();
+        let last2 = if tokens.len() > 1 { tokens.get(tokens.len()-2).cloned() } else { None };
+
+        let mut tokens3: Vec<String> = vec![];
+        tokens3.extend(self.prev2.take());
+        tokens3.extend(self.prev1.take());
+        let first2 = tokens3.len().saturating_sub(1);
+        tokens3.extend(tokens);
+        tokens3.extend(next1);
+        let last2_end = tokens3.len();
+        tokens3.extend(next2);
+
+        // the 2-grams skip prev2 and next2
+        let doubles = tokens3[first2..last2_end].windows(2).map(|d| format!("{}^{}", d[0], d[1])).collect();
+        let triples = tokens3.windows(3).map(|t| format!("{}^{}^{}", t[0], t[1], t[2])).collect();
+        (self.prev1, self.prev2) = (last1, last2);
+        (at, doubles, triples)
+    }
+}
+
+/// Double/triple dictionaries that only count the lines whose clock value lies within `width`
+/// of the newest line. The clock is up to the caller: line numbers give a "last N lines"
+/// window, seconds give a "last T seconds" one.
+pub struct WindowedDictionary {
+    width: i64,
+    stream: LineGrams,
+    history: VecDeque<(i64, Vec<String>, Vec<String>)>,
+    pub double_dict: HashMap<String, i32>,
+    pub triple_dict: HashMap<String, i32>,
+}
+
+impl WindowedDictionary {
+    pub fn new(width: i64) -> WindowedDictionary {
+        WindowedDictionary { width, stream: LineGrams::new(), history: VecDeque::new(), double_dict: HashMap::new(), triple_dict: HashMap::new() }
+    }
+
+    pub fn push_line(&mut self, tokens: Vec<String>, at: i64) {
+        if let Some(g) = self.stream.push(tokens, at) {
+            self.add(g);
+        }
+        self.evict(at);
+    }
+
+    /// Counts the last line, which has been waiting for a lookahead line.
+    pub fn finish(&mut self) {
+        if let Some(g) = self.stream.finish() {
+            let at = g.0;
+            self.add(g);
+            self.evict(at);
+        }
+    }
+
+    fn add(&mut self, (at, doubles, triples): (i64, Vec<String>, Vec<String>)) {
+        doubles.iter().for_each(|d| *self.double_dict.entry(d.clone()).or_default() += 1);
+        triples.iter().for_each(|t| *self.triple_dict.entry(t.clone()).or_default() += 1);
+        self.history.push_back((at, doubles, triples));
+    }
+
+    fn evict(&mut self, now: i64) {
+        while self.history.front().is_some_and(|(at, _, _)| *at <= now - self.width) {
+            let (_, doubles, triples) = self.history.pop_front().unwrap();
+            doubles.iter().for_each(|d| decrement(&mut self.double_dict, d));
+            triples.iter().for_each(|t| decrement(&mut self.triple_dict, t));
+        }
+    }
+}
+
+fn decrement(d: &mut HashMap<String, i32>, key: &str) {
+    let count = d.get_mut(key).unwrap();
+    *count -= 1;
+    if *count == 0 {
+        d.remove(key);
+    }
+}
+
+/// Double/triple dictionaries whose counts halve every `half_life` clock units, so that
+/// recent behaviour outweighs old behaviour.
+pub struct DecayedDictionary {
+    half_life: f64,
+    stream: LineGrams,
+    // counts are stored scaled up by 2^((at - origin)/half_life) so that decaying is free;
+    // rescale() brings origin forward before the scale overflows
+    origin: i64,
+    now: i64,
+    double_dict: HashMap<String, f64>,
+    triple_dict: HashMap<String, f64>,
+}
+
+// counts that have decayed below this are dropped when rescaling
+const FORGOTTEN: f64 = 1e-3;
+
+impl DecayedDictionary {
+    pub fn new(half_life: f64) -> DecayedDictionary {
+        DecayedDictionary { half_life, stream: LineGrams::new(), origin: 0, now: 0, double_dict: HashMap::new(), triple_dict: HashMap::new() }
+    }
+
+    fn scale(&self, at: i64) -> f64 {
+        ((at - self.origin) as f64 / self.half_life).exp2()
+    }
+
+    pub fn push_line(&mut self, tokens: Vec<String>, at: i64) {
+        if let Some(g) = self.stream.push(tokens, at) {
+            self.add(g);
+        }
+        self.now = self.now.max(at);
+    }
+
+    pub fn finish(&mut self) {
+        if let Some(g) = self.stream.finish() {
+            self.add(g);
+        }
+    }
+
+    fn add(&mut self, (at, doubles, triples): (i64, Vec<String>, Vec<String>)) {
+        if self.scale(at) > 1e100 {
+            self.rescale(at);
+        }
+        let weight = self.scale(at);
+        doubles.into_iter().for_each(|d| *self.double_dict.entry(d).or_default() += weight);
+        triples.into_iter().for_each(|t| *self.triple_dict.entry(t).or_default() += weight);
+    }
+
+    fn rescale(&mut self, at: i64) {
+        let factor = self.scale(at);
+        for d in [&mut self.double_dict, &mut self.triple_dict] {
+            d.values_mut().for_each(|v| *v /= factor);
+            d.retain(|_, v| *v >= FORGOTTEN);
+        }
+        self.origin = at;
+    }
+
+    /// Decayed counts rounded down. Cutoffs are integers, so `floor(c) < cutoff` exactly when
+    /// `c < cutoff` and the dynamic-token classifier gives the same answer as on the real counts.
+    /// N-grams that have decayed below 1 are left out, as if they had fallen out of a window.
+    pub fn snapshot(&self) -> (HashMap<String, i32>, HashMap<String, i32>) {
+        let s = self.scale(self.now);
+        let floor = |d: &HashMap<String, f64>| d.iter()
+            .map(|(k, v)| (k.clone(), (v / s).floor() as i32))
+            .filter(|(_, c)| *c > 0)
+            .collect();
+        (floor(&self.double_dict), floor(&self.triple_dict))
+    }
+}
+
+/// How `streaming_parse_raw` counts n-grams. The half-life runs on line numbers.
+pub enum Counting {
+    LastLines(i64),
+    /// the lines of the last this many milliseconds, by their header timestamps
+    LastTime(i64),
+    HalfLife(f64),
+}
+
+// the dictionary `streaming_parse_raw` counts into
+enum Counts {
+    Windowed(WindowedDictionary),
+    Decayed(DecayedDictionary),
+}
+
+impl Counts {
+    fn push_line(&mut self, tokens: Vec<String>, at: i64) {
+        match self {
+            Counts::Windowed(d) => d.push_line(tokens, at),
+            Counts::Decayed(d) => d.push_line(tokens, at),
+        }
+    }
+
+    fn snapshot(mut self) -> (HashMap<String, i32>, HashMap<String, i32>) {
+        match &mut self {
+            Counts::Windowed(d) => {
+                d.finish();
+                (std::mem::take(&mut d.double_dict), std::mem::take(&mut d.triple_dict))
+            }
+            Counts::Decayed(d) => {
+                d.finish();
+                d.snapshot()
+            }
+        }
+    }
+}
+
+/// Like `parse_raw`, but reads the file a line at a time and returns the windowed or decayed
+/// counts as of its last line. `timestamps` reads the line times for Counting::LastTime: a line
+/// without one takes the previous line's, and lines before the first timestamp take that one.
+pub fn streaming_parse_raw(raw_fn: &str, config: &FormatConfig, timestamps: &TimestampParser, counting: &Counting) -> io::Result<Dictionaries> {
+    let mut all_token_list: Vec<String> = vec![];
+    let mut counts = match counting {
+        Counting::LastLines(width) | Counting::LastTime(width) => Counts::Windowed(WindowedDictionary::new(*width)),
+        Counting::HalfLife(h) => Counts::Decayed(DecayedDictionary::new(*h)),
+    };
+    let mut clock = timestamps.stream();
+    let mut last = None;
+    // lines waiting for the first timestamp
+    let mut untimed = vec![];
+    for (i, bytes) in BufReader::new(File::open(raw_fn)?).split(b'\n').enumerate() {
+        let mut bytes = bytes?;
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        let line = String::from_utf8_lossy(&bytes).to_string();
+        let at = match counting {
+            Counting::LastTime(_) => {
+                last = clock.read(&line).or(last);
+                last
+            }
+            _ => Some(i as i64),
+        };
+        let tokens = token_splitter(line, config);
+        tokens.iter().for_each(|t| if !all_token_list.contains(t) { all_token_list.push(t.clone()) });
+        match at {
+            Some(at) => {
+                untimed.drain(..).for_each(|t| counts.push_line(t, at));
+                counts.push_line(tokens, at);
+            }
+            None => untimed.push(tokens),
+        }
+    }
+    untimed.into_iter().for_each(|t| counts.push_line(t, 0));
+
+    let (double_dict, triple_dict) = counts.snapshot();
+    println!("double dictionary list len {}, triple {}, all tokens {}", double_dict.len(), triple_dict.len(), all_token_list.len());
+    Ok((double_dict, triple_dict, all_token_list))
+}
+
+#[test]
+fn test_window_wider_than_file_matches_dictionary_builder() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::dictionary_builder;
+
+    let (dbl, trpl, all_token_list) = streaming_parse_raw("data/Linux_2k.log", &FormatConfig::new(&Linux), &TimestampParser::new(&Linux, 2005), &Counting::LastLines(1_000_000)).unwrap();
+    let (full_dbl, full_trpl, full_all_token_list) = dictionary_builder("data/Linux_2k.log".to_string(), FormatConfig::new(&Linux));
+    assert_eq!(dbl, full_dbl);
+    assert_eq!(trpl, full_trpl);
+    assert_eq!(all_token_list, full_all_token_list);
+}
+
+#[test]
+fn test_windowed_and_decayed_counts() {
+    let line = |s: &str| s.split_whitespace().map(|t| t.to_string()).collect::<Vec<String>>();
+
+    let mut w = WindowedDictionary::new(2);
+    w.push_line(line("a b c"), 0);
+    w.push_line(line("d e"), 1);
+    w.push_line(line("f g"), 2);
+    w.finish();
+    // "a b c" has fallen out of the window, but "c^d" was also counted by "d e"
+    assert_eq!(w.double_dict.get("a^b"), None);
+    assert_eq!(w.double_dict.get("c^d"), Some(&1));
+    assert_eq!(w.double_dict.get("e^f"), Some(&2));
+    assert_eq!(w.triple_dict.get("a^b^c"), None);
+    assert_eq!(w.triple_dict.get("b^c^d"), Some(&1));
+
+    let mut d = DecayedDictionary::new(2.0);
+    d.push_line(line("a b"), 0);
+    d.push_line(line("a b"), 1);
+    d.push_line(line("a b"), 2);
+    d.finish();
+    // 1/2 + 1/sqrt(2) + 1, rounded down
+    assert_eq!(d.snapshot().0.get("a^b"), Some(&2));
+
+    // decayed to 1/4, so gone rather than a count of 0
+    let mut d = DecayedDictionary::new(1.0);
+    d.push_line(line("x y"), 0);
+    d.push_line(line("a b"), 1);
+    d.push_line(line("a b"), 2);
+    d.finish();
+    let (dbl, trpl) = d.snapshot();
+    assert_eq!(dbl.get("x^y"), None);
+    assert_eq!(trpl.get("x^y^a"), None);
+    assert!(dbl.values().chain(trpl.values()).all(|c| *c > 0));
+}
+
+#[test]
+fn test_time_window() {
+    use crate::LogFormat::Linux;
+
+    // the lines without a header take 15:16:00, from the line after and before them
+    let lines = ["  y z", "Jun 14 15:16:00 combo sshd: a b", "  c d", "Jun 14 15:20:00 combo sshd: e f", "Jun 14 15:21:00 combo sshd: g h"];
+    let path = std::env::temp_dir().join(format!("logram-window-{}.log", std::process::id()));
+    std::fs::write(&path, lines.join("\r\n")).unwrap();
+    let window = |width| streaming_parse_raw(path.to_str().unwrap(), &FormatConfig::new(&Linux), &TimestampParser::new(&Linux, 2005), &Counting::LastTime(width)).unwrap().0;
+    // the last two minutes hold only the last two lines
+    let dbl = window(120_000);
+    assert_eq!(dbl.get("a^b"), None);
+    assert_eq!(dbl.get("e^f"), Some(&1));
+    assert_eq!(dbl.get("g^h"), Some(&1));
+    let dbl = window(600_000);
+    assert_eq!(dbl.get("a^b"), Some(&1));
+    assert_eq!(dbl.get("f^g"), Some(&2));
+    std::fs::remove_file(&path).unwrap();
+}